@@ -0,0 +1,35 @@
+#!/usr/bin/env bash
+# Times loading a synthetic event log into Postgres with INSERT statements
+# and with COPY, then exporting it to Parquet, and prints the row counts.
+# Needs a release build, python3, and a Postgres server reachable through
+# the usual PG* environment variables, where it creates and drops the
+# gs_index_bench_* databases.
+#
+#   cargo build --release
+#   PGHOST=localhost PGUSER=postgres PGPASSWORD=postgres scripts/bench.sh 1000000
+set -euo pipefail
+
+events=${1:-1000000}
+gs_index=${GS_INDEX:-target/release/gs-index}
+work=$(mktemp -d)
+trap 'rm -rf "$work"' EXIT
+
+"$(dirname "$0")/synthetic-events.py" "$events" > "$work/events.ndjson"
+
+for mode in statements copy; do
+  flags=(--create-tables --batch-size 10000)
+  if [ "$mode" = copy ]; then
+    flags+=(--copy)
+  fi
+  database=gs_index_bench_$mode
+  dropdb --if-exists "$database"
+  createdb "$database"
+  echo "== $mode: gs-index ${flags[*]} | psql"
+  time "$gs_index" "${flags[@]}" < "$work/events.ndjson" |
+    psql -q -v ON_ERROR_STOP=1 "$database" > /dev/null
+  psql -qAt -F ' ' "$database" -c "SELECT 'project', COUNT(*) FROM project UNION ALL SELECT 'round', COUNT(*) FROM round UNION ALL SELECT 'application', COUNT(*) FROM application"
+  dropdb "$database"
+done
+
+echo "== gs-index export --format parquet"
+time "$gs_index" export --format parquet --out "$work/tables" < "$work/events.ndjson"
@@ -0,0 +1,70 @@
+#!/usr/bin/env python3
+"""Writes a synthetic event log for benchmarks to stdout, as NDJSON events.
+
+Every 1000 events a round is created. Each project is created and applies
+twice to the latest round, and every second project gets an owner. The log
+is the same on every run.
+
+    scripts/synthetic-events.py 1000000 > synthetic.ndjson
+"""
+import json
+import sys
+
+REGISTRY = "0x6294bed5b884ae18bf737793ef9415069bf4bc11"
+
+
+def events(count):
+    block, project, round_address = 1, 0, None
+    emitted, next_round = 0, 0
+
+    def event(address, log_index, data):
+        return {
+            "chainId": 1,
+            "address": address,
+            "blockNumber": block,
+            "logIndex": log_index,
+            "data": data,
+        }
+
+    while True:
+        batch = []
+        if emitted >= next_round:
+            next_round += 1000
+            round_address = "0x%040x" % (emitted + 1)
+            batch.append(event(REGISTRY, 0, {"type": "RoundCreated", "roundAddress": round_address}))
+        project += 1
+        batch.append(event(REGISTRY, 1, {"type": "ProjectCreated", "projectID": hex(project)}))
+        for log_index, index in [(2, project), (3, project + 10**9)]:
+            batch.append(
+                event(
+                    round_address,
+                    log_index,
+                    {
+                        "type": "NewProjectApplication",
+                        "projectID": "0x%064x" % project,
+                        "applicationIndex": hex(index),
+                    },
+                )
+            )
+        if project % 2 == 0:
+            batch.append(
+                event(REGISTRY, 4, {"type": "OwnerAdded", "projectID": hex(project), "owner": REGISTRY})
+            )
+        for item in batch:
+            if emitted == count:
+                return
+            yield item
+            emitted += 1
+        block += 1
+
+
+def main():
+    count = int(sys.argv[1]) if len(sys.argv) > 1 else 1_000_000
+    out = sys.stdout
+    for item in events(count):
+        out.write(json.dumps(item))
+        out.write("\n")
+
+
+if __name__ == "__main__":
+    main()
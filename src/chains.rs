@@ -0,0 +1,128 @@
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+pub const DEFAULT_PAGE_SIZE: u64 = 10_000;
+
+/// Where the events of a chain come from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChainSource {
+    /// JSON-RPC node over HTTP, indexed up to the latest block
+    Rpc { url: String },
+    /// JSON-RPC node over WebSocket, followed as new blocks come in
+    Ws { url: String },
+    /// NDJSON file of gs-log events; events of other chains are ignored
+    Events { path: String },
+    /// NDJSON file of raw EVM logs
+    RawLogs { path: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChainConfig {
+    pub id: u64,
+    pub name: String,
+    pub source: ChainSource,
+    pub registry: Option<Address>,
+    pub round_factory: Option<Address>,
+    /// Rounds to index besides those created by the factory
+    #[serde(default)]
+    pub rounds: Vec<Address>,
+    #[serde(default)]
+    pub start_block: u64,
+    /// Only used with `rpc` sources
+    pub end_block: Option<u64>,
+    #[serde(default = "default_page_size")]
+    pub page_size: u64,
+}
+
+fn default_page_size() -> u64 {
+    DEFAULT_PAGE_SIZE
+}
+
+impl ChainConfig {
+    /// Contracts configured for the chain, as opposed to those discovered
+    /// while indexing.
+    pub fn addresses(&self) -> Vec<Address> {
+        self.registry
+            .into_iter()
+            .chain(self.round_factory)
+            .chain(self.rounds.iter().copied())
+            .collect()
+    }
+}
+
+/// Reads a JSON array of chain configurations.
+pub fn load_chains(path: &Path) -> io::Result<Vec<ChainConfig>> {
+    let chains: Vec<ChainConfig> = serde_json::from_reader(File::open(path)?)?;
+    for (position, chain) in chains.iter().enumerate() {
+        if chains[..position].iter().any(|other| other.id == chain.id) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("chain {} is configured more than once", chain.id),
+            ));
+        }
+    }
+    Ok(chains)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_load_chains() {
+        let mut chains_file = NamedTempFile::new().unwrap();
+        write!(
+            chains_file,
+            r#"[
+                {{
+                    "id": 1,
+                    "name": "mainnet",
+                    "source": {{ "type": "rpc", "url": "http://localhost:8545" }},
+                    "registry": "0x5FbDB2315678afecb367f032d93F642f64180aa3",
+                    "start_block": 16000000
+                }},
+                {{
+                    "id": 10,
+                    "name": "optimism",
+                    "source": {{ "type": "events", "path": "optimism.ndjson" }}
+                }}
+            ]"#
+        )
+        .unwrap();
+
+        let chains = load_chains(chains_file.path()).unwrap();
+        assert_eq!(chains.len(), 2);
+        assert_eq!(
+            chains[0].source,
+            ChainSource::Rpc {
+                url: "http://localhost:8545".to_string()
+            }
+        );
+        assert_eq!(chains[0].addresses().len(), 1);
+        assert_eq!(chains[0].start_block, 16000000);
+        assert_eq!(chains[0].page_size, DEFAULT_PAGE_SIZE);
+        assert_eq!(chains[1].start_block, 0);
+        assert!(chains[1].addresses().is_empty());
+    }
+
+    #[test]
+    fn test_load_chains_rejects_duplicate_ids() {
+        let mut chains_file = NamedTempFile::new().unwrap();
+        write!(
+            chains_file,
+            r#"[
+                {{ "id": 1, "name": "a", "source": {{ "type": "events", "path": "a.ndjson" }} }},
+                {{ "id": 1, "name": "b", "source": {{ "type": "events", "path": "b.ndjson" }} }}
+            ]"#
+        )
+        .unwrap();
+
+        assert!(load_chains(chains_file.path()).is_err());
+    }
+}
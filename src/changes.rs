@@ -0,0 +1,93 @@
+//! Changes to the indexed tables, as built by the event handlers. They do
+//! not depend on the database they are applied to; see `dialect` for their
+//! SQL rendering.
+
+use ethers::types::U256;
+use sea_query::DynIden;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Value {
+    Null,
+    /// Block numbers, chain ids and counters
+    Integer(u64),
+    /// uint256 values
+    Numeric(U256),
+    Text(String),
+    /// A JSON document
+    Json(String),
+    /// Texts indexed for full-text search, most important first
+    Search(Vec<String>),
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<U256> for Value {
+    fn from(value: U256) -> Self {
+        Value::Numeric(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Text(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+/// Column values, in column order.
+pub type Row = Vec<(DynIden, Value)>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Insert {
+        table: DynIden,
+        values: Row,
+    },
+    /// Sets `values` on the rows matching `key`
+    Update {
+        table: DynIden,
+        key: Row,
+        values: Row,
+    },
+    /// Inserts `key` and `values`, or sets `values` on the row with the same
+    /// `key` if there is one
+    Upsert {
+        table: DynIden,
+        key: Row,
+        values: Row,
+    },
+    Delete {
+        table: DynIden,
+        key: Row,
+    },
+    /// Appends `item` to the JSON array of strings in `column`
+    AddToList {
+        table: DynIden,
+        key: Row,
+        column: DynIden,
+        item: String,
+    },
+    /// Removes every occurrence of `item` from the JSON array of strings in
+    /// `column`
+    RemoveFromList {
+        table: DynIden,
+        key: Row,
+        column: DynIden,
+        item: String,
+    },
+}
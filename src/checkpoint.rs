@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+use crate::contract_watchlist::WatchedContract;
+use crate::event_handling::Event;
+
+/// Position of the last event indexed on a chain.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Checkpoint {
+    pub block_number: u64,
+    pub log_index: u64,
+}
+
+impl Checkpoint {
+    pub fn is_before(&self, event: &Event) -> bool {
+        (self.block_number, self.log_index) < (event.block_number, event.log_index)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+struct CheckpointState {
+    chains: BTreeMap<u64, Checkpoint>,
+    /// Contracts discovered while indexing, so that they are still indexed
+    /// after resuming.
+    contracts: Vec<WatchedContract>,
+}
+
+/// Per-chain checkpoints persisted as a JSON file, written atomically so
+/// that an interrupted save never leaves a truncated file behind. The
+/// default store has no file and is only kept in memory.
+#[derive(Default)]
+pub struct CheckpointStore {
+    path: Option<PathBuf>,
+    state: CheckpointState,
+}
+
+impl CheckpointStore {
+    /// Loads checkpoints from `path`, starting afresh if the file does not
+    /// exist yet.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let state = match File::open(path) {
+            Ok(file) => serde_json::from_reader(file)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => CheckpointState::default(),
+            Err(err) => return Err(err),
+        };
+        Ok(CheckpointStore {
+            path: Some(path.to_path_buf()),
+            state,
+        })
+    }
+
+    pub fn checkpoint(&self, chain_id: u64) -> Option<Checkpoint> {
+        self.state.chains.get(&chain_id).copied()
+    }
+
+    pub fn contracts(&self) -> &[WatchedContract] {
+        &self.state.contracts
+    }
+
+    pub fn update(&mut self, event: &Event) {
+        self.state.chains.insert(
+            event.chain_id,
+            Checkpoint {
+                block_number: event.block_number,
+                log_index: event.log_index,
+            },
+        );
+    }
+
+    pub fn set_contracts(&mut self, contracts: Vec<WatchedContract>) {
+        self.state.contracts = contracts;
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut file = BufWriter::new(NamedTempFile::new_in(directory)?);
+        serde_json::to_writer_pretty(&mut file, &self.state)?;
+        file.flush()?;
+        let file = file.into_inner().map_err(|err| err.into_error())?;
+        file.persist(path).map_err(|err| err.error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handling::EventPayload;
+    use ethers::types::{Address, U256};
+    use tempfile::TempDir;
+
+    fn event(chain_id: u64, block_number: u64, log_index: u64) -> Event {
+        Event {
+            chain_id,
+            address: Address::from_low_u64_be(0x123),
+            block_number,
+            log_index,
+            data: EventPayload::ProjectCreated {
+                project_id: U256::from(123),
+            },
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_is_before() {
+        let checkpoint = Checkpoint {
+            block_number: 10,
+            log_index: 2,
+        };
+        assert!(!checkpoint.is_before(&event(1, 9, 5)));
+        assert!(!checkpoint.is_before(&event(1, 10, 2)));
+        assert!(checkpoint.is_before(&event(1, 10, 3)));
+        assert!(checkpoint.is_before(&event(1, 11, 0)));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("checkpoints.json");
+
+        let mut store = CheckpointStore::load(&path).unwrap();
+        assert_eq!(store.checkpoint(1), None);
+
+        store.update(&event(1, 10, 2));
+        store.update(&event(10, 500, 0));
+        store.update(&event(1, 12, 0));
+        store.set_contracts(vec![WatchedContract {
+            chain_id: 1,
+            address: Address::from_low_u64_be(0x456),
+            from_block: 11,
+        }]);
+        store.save().unwrap();
+
+        let store = CheckpointStore::load(&path).unwrap();
+        assert_eq!(
+            store.checkpoint(1),
+            Some(Checkpoint {
+                block_number: 12,
+                log_index: 0
+            })
+        );
+        assert_eq!(
+            store.checkpoint(10),
+            Some(Checkpoint {
+                block_number: 500,
+                log_index: 0
+            })
+        );
+        assert_eq!(store.contracts().len(), 1);
+        assert_eq!(store.contracts()[0].from_block, 11);
+    }
+}
@@ -0,0 +1,454 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::chains::{ChainConfig, ChainSource};
+use crate::dialect::Dialect;
+use crate::event_source::ParseErrorPolicy;
+
+pub const DEFAULT_CONFIG_FILE: &str = "gs-index.toml";
+pub const DEFAULT_IPFS_GATEWAY: &str = "https://d16c97c2np8a2o.cloudfront.net/ipfs/";
+
+/// Everything gs-index can be told through `gs-index.toml`. Command-line
+/// flags and environment variables take precedence over the file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Print the database schema before any change
+    pub create_tables: bool,
+    /// File keeping track of the last indexed event of each chain
+    pub checkpoint_file: Option<PathBuf>,
+    /// How to read stdin when no chains are configured
+    pub input: InputConfig,
+    pub sink: SinkConfig,
+    pub output: OutputConfig,
+    pub ipfs: IpfsConfig,
+    pub errors: ErrorsConfig,
+    pub batching: BatchingConfig,
+    pub chains: Vec<ChainConfig>,
+}
+
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InputFormat {
+    /// Events decoded by gs-log
+    #[default]
+    Events,
+    /// Raw EVM logs (address, topics, data, blockNumber, logIndex)
+    RawLogs,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    pub format: InputFormat,
+    /// Chain the raw logs come from
+    pub chain_id: Option<u64>,
+}
+
+/// Where the SQL goes.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SinkConfig {
+    #[default]
+    Stdout,
+    /// Appends to a file, creating it if needed
+    File { path: PathBuf },
+    /// Applies the changes to a SQLite database file, creating it if needed
+    Sqlite { path: PathBuf },
+}
+
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// SQL statements
+    #[default]
+    Sql,
+    /// One JSON record per event, listing the changes to the tables
+    Json,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub format: OutputFormat,
+    /// SQL dialect of the statements. Defaults to the one of the sink:
+    /// SQLite for a SQLite database, Postgres otherwise.
+    pub dialect: Option<Dialect>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct IpfsConfig {
+    /// Tried in order until one answers
+    pub gateways: Vec<String>,
+    /// Time allowed for a single request to a gateway
+    pub timeout_ms: u64,
+    /// Number of times to go through the gateways again after all failed
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each subsequent retry
+    pub backoff_ms: u64,
+    /// Directory where fetched documents are kept, consulted before the
+    /// gateways
+    pub cache_dir: Option<PathBuf>,
+    /// Size in bytes past which least recently used documents are evicted
+    pub cache_max_size: Option<u64>,
+    /// Number of documents fetched at once, looking ahead in the event stream
+    pub concurrency: usize,
+    /// Resolve documents from local files only, instead of the gateways
+    pub local: Option<LocalIpfsConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LocalIpfsConfig {
+    /// Directory of documents named after their CID, e.g. `ipfs.cache_dir`
+    pub directory: Option<PathBuf>,
+    /// CAR archives holding the blocks of the documents
+    pub car_files: Vec<PathBuf>,
+}
+
+impl Default for IpfsConfig {
+    fn default() -> Self {
+        IpfsConfig {
+            gateways: vec![DEFAULT_IPFS_GATEWAY.to_string()],
+            timeout_ms: 10_000,
+            retries: 3,
+            backoff_ms: 500,
+            cache_dir: None,
+            cache_max_size: None,
+            concurrency: 8,
+            local: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ErrorsConfig {
+    pub on_parse_error: ParseErrorPolicy,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatchingConfig {
+    /// Number of statements to group in a transaction. 0 disables explicit
+    /// transactions.
+    pub size: usize,
+    /// Load the inserts of each batch with COPY blocks for psql, for faster
+    /// backfills
+    pub copy: bool,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, String),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "cannot read {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => write!(f, "cannot parse {}: {}", path.display(), err),
+            ConfigError::Invalid(problems) => {
+                write!(f, "invalid configuration:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let content =
+            fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        toml::from_str(&content)
+            .map_err(|err| ConfigError::Parse(path.to_path_buf(), err.to_string()))
+    }
+
+    /// Reads `path` if given, otherwise `gs-index.toml` in the current
+    /// directory if there is one, otherwise starts from the defaults.
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        match path {
+            Some(path) => Config::from_file(path),
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Config::from_file(Path::new(DEFAULT_CONFIG_FILE))
+            }
+            None => Ok(Config::default()),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = vec![];
+
+        if self.chains.is_empty()
+            && self.input.format == InputFormat::RawLogs
+            && self.input.chain_id.is_none()
+        {
+            problems.push("input.chain_id is required to read raw logs".to_string());
+        }
+
+        if let SinkConfig::File { path } | SinkConfig::Sqlite { path } = &self.sink {
+            if path.as_os_str().is_empty() {
+                problems.push("sink.path must not be empty".to_string());
+            }
+        }
+        if matches!(self.sink, SinkConfig::Sqlite { .. })
+            && self
+                .output
+                .dialect
+                .is_some_and(|dialect| dialect != Dialect::Sqlite)
+        {
+            problems.push("a sqlite sink needs the sqlite output.dialect".to_string());
+        }
+        if self.output.format == OutputFormat::Json {
+            if matches!(self.sink, SinkConfig::Sqlite { .. }) {
+                problems.push("a sqlite sink needs the sql output.format".to_string());
+            }
+            if self.batching.copy {
+                problems.push("batching.copy is only supported with SQL output".to_string());
+            }
+        }
+        if self.batching.copy
+            && (self.dialect() != Dialect::Postgres
+                || matches!(self.sink, SinkConfig::Sqlite { .. }))
+        {
+            problems.push("batching.copy is only supported with Postgres output".to_string());
+        }
+        if self.batching.copy && self.batching.size == 0 {
+            problems.push("batching.copy needs a batching.size".to_string());
+        }
+
+        if self.ipfs.gateways.is_empty() {
+            problems.push("ipfs.gateways must list at least one gateway".to_string());
+        }
+        for gateway in &self.ipfs.gateways {
+            match reqwest::Url::parse(gateway) {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+                _ => problems.push(format!("{} is not a valid IPFS gateway URL", gateway)),
+            }
+        }
+        if let Some(local) = &self.ipfs.local {
+            if local.directory.is_none() && local.car_files.is_empty() {
+                problems.push("ipfs.local needs a directory or car_files".to_string());
+            }
+        }
+        if self.ipfs.concurrency == 0 {
+            problems.push("ipfs.concurrency must be greater than 0".to_string());
+        }
+        if self.ipfs.timeout_ms == 0 {
+            problems.push("ipfs.timeout_ms must be greater than 0".to_string());
+        }
+
+        for (position, chain) in self.chains.iter().enumerate() {
+            if self.chains[..position]
+                .iter()
+                .any(|other| other.id == chain.id)
+            {
+                problems.push(format!("chain {} is configured more than once", chain.id));
+            }
+            let is_node = matches!(
+                chain.source,
+                ChainSource::Rpc { .. } | ChainSource::Ws { .. }
+            );
+            if is_node && chain.addresses().is_empty() {
+                problems.push(format!(
+                    "chain {} ({}) has no registry, round factory or rounds to index",
+                    chain.id, chain.name
+                ));
+            }
+            let node = match &chain.source {
+                ChainSource::Rpc { url } => Some((url, ["http", "https"])),
+                ChainSource::Ws { url } => Some((url, ["ws", "wss"])),
+                ChainSource::Events { .. } | ChainSource::RawLogs { .. } => None,
+            };
+            if let Some((url, schemes)) = node {
+                if !reqwest::Url::parse(url).is_ok_and(|url| schemes.contains(&url.scheme())) {
+                    problems.push(format!(
+                        "chain {} ({}) has an invalid {} URL {}",
+                        chain.id, chain.name, schemes[0], url
+                    ));
+                }
+            }
+            if chain.page_size == 0 {
+                problems.push(format!(
+                    "chain {} ({}) has a page_size of 0",
+                    chain.id, chain.name
+                ));
+            }
+            if chain
+                .end_block
+                .is_some_and(|end_block| end_block < chain.start_block)
+            {
+                problems.push(format!(
+                    "chain {} ({}) ends before it starts",
+                    chain.id, chain.name
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.output.dialect.unwrap_or(match self.sink {
+            SinkConfig::Sqlite { .. } => Dialect::Sqlite,
+            _ => Dialect::Postgres,
+        })
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("configuration can always be serialized")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::Address;
+
+    const SAMPLE_CONFIG: &str = r#"
+create_tables = true
+checkpoint_file = "checkpoints.json"
+
+[sink]
+type = "file"
+path = "out.sql"
+
+[ipfs]
+gateways = ["https://ipfs.io/ipfs/", "https://cloudflare-ipfs.com/ipfs/"]
+timeout_ms = 5000
+cache_dir = "ipfs-cache"
+
+[errors]
+on_parse_error = "warn"
+
+[batching]
+size = 500
+
+[[chains]]
+id = 1
+name = "mainnet"
+source = { type = "rpc", url = "http://localhost:8545" }
+registry = "0x5FbDB2315678afecb367f032d93F642f64180aa3"
+round_factory = "0xe7f1725E7734CE288F8367e1Bb143E90bb3F0512"
+start_block = 16000000
+
+[[chains]]
+id = 10
+name = "optimism"
+source = { type = "events", path = "optimism.ndjson" }
+"#;
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = toml::from_str(SAMPLE_CONFIG).unwrap();
+        assert!(config.create_tables);
+        assert_eq!(
+            config.sink,
+            SinkConfig::File {
+                path: PathBuf::from("out.sql")
+            }
+        );
+        assert_eq!(config.ipfs.gateways.len(), 2);
+        assert_eq!(config.ipfs.timeout_ms, 5000);
+        assert_eq!(config.ipfs.retries, IpfsConfig::default().retries);
+        assert_eq!(config.ipfs.cache_dir, Some(PathBuf::from("ipfs-cache")));
+        assert_eq!(config.errors.on_parse_error, ParseErrorPolicy::Warn);
+        assert_eq!(config.batching.size, 500);
+        assert_eq!(config.chains.len(), 2);
+        assert_eq!(
+            config.chains[0].registry,
+            Some(
+                "0x5FbDB2315678afecb367f032d93F642f64180aa3"
+                    .parse::<Address>()
+                    .unwrap()
+            )
+        );
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_empty_config_uses_defaults() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.sink, SinkConfig::Stdout);
+        assert_eq!(config.ipfs.gateways, vec![DEFAULT_IPFS_GATEWAY.to_string()]);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        assert!(toml::from_str::<Config>("[batching]\nsise = 10").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut config: Config = toml::from_str(SAMPLE_CONFIG).unwrap();
+        config.chains[1].id = 1;
+        config.chains[0].registry = None;
+        config.chains[0].round_factory = None;
+        config.ipfs.gateways.push("not a url".to_string());
+        config.chains[0].source = ChainSource::Ws {
+            url: "http://localhost:8545".to_string(),
+        };
+
+        let Err(ConfigError::Invalid(problems)) = config.validate() else {
+            panic!("configuration should be invalid");
+        };
+        assert_eq!(
+            problems,
+            vec![
+                "not a url is not a valid IPFS gateway URL",
+                "chain 1 (mainnet) has no registry, round factory or rounds to index",
+                "chain 1 (mainnet) has an invalid ws URL http://localhost:8545",
+                "chain 1 is configured more than once",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dialect_follows_sink() {
+        let mut config: Config =
+            toml::from_str("[sink]\ntype = \"sqlite\"\npath = \"gs.sqlite\"").unwrap();
+        assert_eq!(config.dialect(), Dialect::Sqlite);
+        assert!(config.validate().is_ok());
+
+        config.output.dialect = Some(Dialect::Postgres);
+        assert!(config.validate().is_err());
+        config.sink = SinkConfig::Stdout;
+        assert_eq!(config.dialect(), Dialect::Postgres);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_json_output() {
+        let mut config: Config = toml::from_str("[output]\nformat = \"json\"").unwrap();
+        assert_eq!(config.output.format, OutputFormat::Json);
+        assert!(config.validate().is_ok());
+
+        config.sink = SinkConfig::Sqlite {
+            path: PathBuf::from("gs.sqlite"),
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_to_toml_round_trip() {
+        let config: Config = toml::from_str(SAMPLE_CONFIG).unwrap();
+        let reparsed: Config = toml::from_str(&config.to_toml()).unwrap();
+        assert_eq!(reparsed, config);
+    }
+}
@@ -0,0 +1,125 @@
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+use crate::event_handling::Event;
+
+/// A contract whose logs should be indexed starting at `from_block`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchedContract {
+    pub chain_id: u64,
+    pub address: Address,
+    pub from_block: u64,
+}
+
+/// Contracts discovered while indexing, e.g. rounds announced by
+/// `RoundCreated`. The indexer registers the contracts reported by event
+/// handlers; log-fetching sources hold a clone, pick up new entries between
+/// items and backfill them from their creation block.
+#[derive(Clone, Default)]
+pub struct ContractWatchlist {
+    contracts: Arc<Mutex<Vec<WatchedContract>>>,
+}
+
+/// Position of a reader in the watchlist, so that each source sees every
+/// contract exactly once.
+#[derive(Default)]
+pub struct WatchlistCursor {
+    position: usize,
+}
+
+impl ContractWatchlist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts with contracts discovered in a previous run.
+    pub fn with_contracts(contracts: Vec<WatchedContract>) -> Self {
+        ContractWatchlist {
+            contracts: Arc::new(Mutex::new(contracts)),
+        }
+    }
+
+    pub fn contracts(&self) -> Vec<WatchedContract> {
+        self.contracts.lock().unwrap().clone()
+    }
+
+    /// Registers contracts discovered by handling `event`. Contracts already
+    /// on the list are ignored.
+    pub fn register(&self, event: &Event, addresses: &[Address]) {
+        let mut contracts = self.contracts.lock().unwrap();
+        for address in addresses {
+            let is_known = contracts.iter().any(|contract| {
+                contract.chain_id == event.chain_id && contract.address == *address
+            });
+            if !is_known {
+                contracts.push(WatchedContract {
+                    chain_id: event.chain_id,
+                    address: *address,
+                    from_block: event.block_number,
+                });
+            }
+        }
+    }
+
+    /// Returns the contracts on `chain_id` registered since the cursor was
+    /// last advanced.
+    pub fn take_new(&self, chain_id: u64, cursor: &mut WatchlistCursor) -> Vec<WatchedContract> {
+        let contracts = self.contracts.lock().unwrap();
+        let new_contracts = contracts[cursor.position..]
+            .iter()
+            .filter(|contract| contract.chain_id == chain_id)
+            .cloned()
+            .collect();
+        cursor.position = contracts.len();
+        new_contracts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handling::EventPayload;
+
+    fn round_created(chain_id: u64, block_number: u64, round_address: Address) -> Event {
+        Event {
+            chain_id,
+            address: Address::from_low_u64_be(0x123),
+            block_number,
+            log_index: 0,
+            data: EventPayload::RoundCreated { round_address },
+        }
+    }
+
+    #[test]
+    fn test_take_new_contracts() {
+        let watchlist = ContractWatchlist::new();
+        let mut cursor = WatchlistCursor::default();
+        let round_a = Address::from_low_u64_be(0xa);
+        let round_b = Address::from_low_u64_be(0xb);
+
+        watchlist.register(&round_created(1, 10, round_a), &[round_a]);
+        watchlist.register(&round_created(10, 11, round_a), &[round_a]);
+        assert_eq!(
+            watchlist.take_new(1, &mut cursor),
+            vec![WatchedContract {
+                chain_id: 1,
+                address: round_a,
+                from_block: 10,
+            }]
+        );
+        assert_eq!(watchlist.take_new(1, &mut cursor), vec![]);
+
+        watchlist.register(&round_created(1, 20, round_a), &[round_a]);
+        watchlist.register(&round_created(1, 30, round_b), &[round_b]);
+        assert_eq!(
+            watchlist.take_new(1, &mut cursor),
+            vec![WatchedContract {
+                chain_id: 1,
+                address: round_b,
+                from_block: 30,
+            }]
+        );
+    }
+}
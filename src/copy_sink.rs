@@ -0,0 +1,283 @@
+use sea_query::DynIden;
+use std::io::{self, Write};
+
+use crate::changes::{Change, Value};
+use crate::dialect::Dialect;
+use crate::event_handling::Event;
+use crate::sink::Sink;
+
+/// Rows to insert into `table`, in COPY text format.
+struct PendingCopy {
+    table: DynIden,
+    columns: Vec<DynIden>,
+    rows: Vec<String>,
+}
+
+/// Postgres output for bulk loads, which inserts rows with `COPY ... FROM
+/// STDIN` blocks that psql reads inline. Inserts into a table are buffered
+/// until the end of the batch, or until another kind of change touches the
+/// table so that changes still apply in event order; those other changes are
+/// written as statements.
+pub struct CopySink<W: Write> {
+    writer: W,
+    batch_size: usize,
+    change_sets_in_batch: usize,
+    /// In the order their first row was buffered
+    copies: Vec<PendingCopy>,
+}
+
+/// `value` in COPY text format, if it can be copied as is.
+fn copy_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => Some("\\N".to_string()),
+        Value::Integer(value) => Some(value.to_string()),
+        Value::Numeric(value) => Some(value.to_string()),
+        Value::Text(value) | Value::Json(value) => {
+            let mut escaped = String::with_capacity(value.len());
+            for c in value.chars() {
+                match c {
+                    '\\' => escaped.push_str("\\\\"),
+                    '\t' => escaped.push_str("\\t"),
+                    '\n' => escaped.push_str("\\n"),
+                    '\r' => escaped.push_str("\\r"),
+                    c => escaped.push(c),
+                }
+            }
+            Some(escaped)
+        }
+        // Computed by the database
+        Value::Search(_) => None,
+    }
+}
+
+impl<W: Write> CopySink<W> {
+    pub fn new(writer: W, batch_size: usize) -> Self {
+        CopySink {
+            writer,
+            batch_size,
+            change_sets_in_batch: 0,
+            copies: vec![],
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_copy(&mut self, copy: PendingCopy) -> io::Result<()> {
+        let columns: Vec<String> = copy
+            .columns
+            .iter()
+            .map(|column| format!("\"{}\"", column.to_string()))
+            .collect();
+        writeln!(
+            self.writer,
+            "COPY \"{}\" ({}) FROM STDIN;",
+            copy.table.to_string(),
+            columns.join(", ")
+        )?;
+        for row in copy.rows {
+            writeln!(self.writer, "{}", row)?;
+        }
+        writeln!(self.writer, "\\.")
+    }
+
+    /// Writes the rows buffered for `table`, if any.
+    fn write_copies_of(&mut self, table: &DynIden) -> io::Result<()> {
+        if let Some(position) = self.copies.iter().position(|copy| &copy.table == table) {
+            let copy = self.copies.remove(position);
+            self.write_copy(copy)?;
+        }
+        Ok(())
+    }
+
+    fn write_all_copies(&mut self) -> io::Result<()> {
+        for copy in std::mem::take(&mut self.copies) {
+            self.write_copy(copy)?;
+        }
+        Ok(())
+    }
+
+    fn write_change(&mut self, change: &Change) -> io::Result<()> {
+        if let Change::Insert { table, values } = change {
+            if let Some(row) = values
+                .iter()
+                .map(|(_, value)| copy_value(value))
+                .collect::<Option<Vec<_>>>()
+            {
+                let columns: Vec<DynIden> =
+                    values.iter().map(|(column, _)| column.clone()).collect();
+                let position = match self.copies.iter().position(|copy| &copy.table == table) {
+                    Some(position) if self.copies[position].columns == columns => position,
+                    existing => {
+                        // Inserting other columns than the buffered rows
+                        if existing.is_some() {
+                            self.write_copies_of(table)?;
+                        }
+                        self.copies.push(PendingCopy {
+                            table: table.clone(),
+                            columns,
+                            rows: vec![],
+                        });
+                        self.copies.len() - 1
+                    }
+                };
+                self.copies[position].rows.push(row.join("\t"));
+                return Ok(());
+            }
+        }
+
+        let table = match change {
+            Change::Insert { table, .. }
+            | Change::Update { table, .. }
+            | Change::Upsert { table, .. }
+            | Change::Delete { table, .. }
+            | Change::AddToList { table, .. }
+            | Change::RemoveFromList { table, .. } => table,
+        };
+        self.write_copies_of(table)?;
+        writeln!(self.writer, "{};", Dialect::Postgres.render(change))
+    }
+}
+
+impl<W: Write> Sink for CopySink<W> {
+    fn write_schema(&mut self) -> io::Result<()> {
+        writeln!(self.writer, "{}", Dialect::Postgres.schema())
+    }
+
+    fn write_changes(&mut self, _event: &Event, changes: &[Change]) -> io::Result<()> {
+        if self.change_sets_in_batch == 0 {
+            writeln!(self.writer, "BEGIN;")?;
+        }
+        for change in changes {
+            self.write_change(change)?;
+        }
+        self.change_sets_in_batch += 1;
+        if self.change_sets_in_batch >= self.batch_size {
+            self.write_all_copies()?;
+            writeln!(self.writer, "COMMIT;")?;
+            self.change_sets_in_batch = 0;
+        }
+        Ok(())
+    }
+
+    /// As with statements, a consumer that never reads the COMMIT rolls the
+    /// transaction back.
+    fn write_atomically(&mut self, change_sets: &[(Event, Vec<Change>)]) -> io::Result<()> {
+        self.finish()?;
+        writeln!(self.writer, "BEGIN;")?;
+        for (_event, changes) in change_sets {
+            for change in changes {
+                self.write_change(change)?;
+            }
+        }
+        self.write_all_copies()?;
+        writeln!(self.writer, "COMMIT;")
+    }
+
+    fn is_in_batch(&self) -> bool {
+        self.change_sets_in_batch > 0
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_all_copies()?;
+        if self.change_sets_in_batch > 0 {
+            writeln!(self.writer, "COMMIT;")?;
+            self.change_sets_in_batch = 0;
+        }
+        self.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handling::{event_to_changeset_with_metadata, EventPayload, MetaPtr};
+    use crate::test_helpers::event;
+    use ethers::types::{Address, U256};
+
+    #[test]
+    fn test_copy_value() {
+        assert_eq!(copy_value(&Value::Null), Some("\\N".to_string()));
+        assert_eq!(
+            copy_value(&Value::Text("a\tb\\c\nd".to_string())),
+            Some("a\\tb\\\\c\\nd".to_string())
+        );
+        assert_eq!(copy_value(&Value::Search(vec![])), None);
+    }
+
+    #[test]
+    fn test_inserts_are_copied_in_event_order() {
+        let mut sink = CopySink::new(vec![], 5);
+        let project_created = |block_number, project_id| {
+            event(
+                block_number,
+                EventPayload::ProjectCreated {
+                    project_id: U256::from(project_id),
+                },
+            )
+        };
+        let round_created = |block_number, round| {
+            event(
+                block_number,
+                EventPayload::RoundCreated {
+                    round_address: Address::from_low_u64_be(round),
+                },
+            )
+        };
+        let events = [
+            (project_created(1, 1), None),
+            (round_created(2, 0xa), None),
+            (project_created(3, 2), None),
+            (
+                event(
+                    4,
+                    EventPayload::MetadataUpdated {
+                        project_id: U256::from(2),
+                        meta_ptr: MetaPtr {
+                            pointer: "cid".to_string(),
+                        },
+                    },
+                ),
+                Some(Ok("<html>".to_string())),
+            ),
+            (round_created(5, 0xb), None),
+            (project_created(6, 3), None),
+        ];
+        for (event, metadata) in events {
+            sink.write_changes(
+                &event,
+                &event_to_changeset_with_metadata(&event, metadata).changes,
+            )
+            .unwrap();
+        }
+        assert!(sink.is_in_batch());
+        sink.finish().unwrap();
+
+        assert_eq!(
+            String::from_utf8(sink.into_inner()).unwrap(),
+            r#"BEGIN;
+COPY "project" ("chain_id", "project_id", "created_at_block") FROM STDIN;
+1	1	1
+1	2	3
+\.
+UPDATE "project" SET "metadata" = NULL, "metadata_status" = 'invalid', "metadata_error" = 'metadata is not valid JSON: expected value at line 1 column 1', "title" = NULL, "description" = NULL, "website" = NULL, "logo_img" = NULL, "banner_img" = NULL, "project_twitter" = NULL, "user_github" = NULL, "project_github" = NULL, "credentials" = NULL, "search" = NULL WHERE "chain_id" = 1 AND "project_id" = (2);
+DELETE FROM "pending_metadata" WHERE "chain_id" = 1 AND "project_id" = (2);
+COPY "round" ("chain_id", "round_address", "created_at_block") FROM STDIN;
+1	0x000000000000000000000000000000000000000a	2
+1	0x000000000000000000000000000000000000000b	5
+\.
+COMMIT;
+BEGIN;
+COPY "project" ("chain_id", "project_id", "created_at_block") FROM STDIN;
+1	3	6
+\.
+COMMIT;
+"#
+        );
+    }
+}
@@ -0,0 +1,481 @@
+use clap::ValueEnum;
+use sea_query::{
+    Alias, BinOper, DynIden, Expr, Func, MysqlQueryBuilder, OnConflict, PostgresQueryBuilder,
+    Query, QueryStatementWriter, SimpleExpr, SqliteQueryBuilder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::changes::{Change, Row, Value};
+use crate::event_handling::DB_SCHEMA;
+
+/// Same tables as `DB_SCHEMA`. uint256 values are stored as decimal text,
+/// since SQLite integers are 64-bit, and JSON documents as text, to be read
+/// with the JSON1 functions. Rounds are keyed by a unique index rather than
+/// a primary key, so that opening a database file created without the key
+/// adds it.
+pub const SQLITE_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS project (chain_id INTEGER NOT NULL, project_id TEXT NOT NULL, created_at_block INTEGER NOT NULL, metadata TEXT, metadata_status TEXT, metadata_error TEXT, title TEXT, description TEXT, website TEXT, logo_img TEXT, banner_img TEXT, project_twitter TEXT, user_github TEXT, project_github TEXT, credentials TEXT, search TEXT, owners TEXT NOT NULL DEFAULT '[]', PRIMARY KEY(chain_id, project_id));
+CREATE TABLE IF NOT EXISTS round (chain_id INTEGER NOT NULL, round_address TEXT NOT NULL, created_at_block INTEGER NOT NULL);
+CREATE UNIQUE INDEX IF NOT EXISTS round_key ON round (chain_id, round_address);
+CREATE TABLE IF NOT EXISTS application (chain_id INTEGER NOT NULL, created_at_block INTEGER NOT NULL, status_updated_at_block INTEGER NOT NULL, project_id TEXT NOT NULL, "index" TEXT NOT NULL, round_address TEXT NOT NULL, status TEXT NOT NULL, vote_count INTEGER NOT NULL, unique_contributor_count INTEGER NOT NULL, metadata TEXT, status_snapshots TEXT, PRIMARY KEY(chain_id, round_address, "index"));
+CREATE TABLE IF NOT EXISTS pending_metadata (chain_id INTEGER NOT NULL, project_id TEXT NOT NULL, cid TEXT NOT NULL, attempts INTEGER NOT NULL, last_error TEXT, PRIMARY KEY(chain_id, project_id));
+"#;
+
+/// Same tables as `DB_SCHEMA`, for MySQL 8 and MariaDB 10.5 or later.
+/// uint256 values are stored as decimal text, as in SQLite, since DECIMAL
+/// holds at most 65 digits and uint256 values take up to 78.
+pub const MYSQL_SCHEMA: &str = r#"
+CREATE TABLE project (chain_id BIGINT UNSIGNED NOT NULL, project_id VARCHAR(78) NOT NULL, created_at_block BIGINT UNSIGNED NOT NULL, metadata JSON, metadata_status VARCHAR(16), metadata_error TEXT, title TEXT, description TEXT, website TEXT, logo_img VARCHAR(255), banner_img VARCHAR(255), project_twitter VARCHAR(255), user_github VARCHAR(255), project_github VARCHAR(255), credentials JSON, search TEXT, owners JSON NOT NULL DEFAULT (JSON_ARRAY()), PRIMARY KEY(chain_id, project_id), FULLTEXT INDEX project_search (search));
+CREATE TABLE round (chain_id BIGINT UNSIGNED NOT NULL, round_address VARCHAR(42) NOT NULL, created_at_block BIGINT UNSIGNED NOT NULL, PRIMARY KEY(chain_id, round_address));
+CREATE TABLE application (chain_id BIGINT UNSIGNED NOT NULL, created_at_block BIGINT UNSIGNED NOT NULL, status_updated_at_block BIGINT UNSIGNED NOT NULL, project_id VARCHAR(66) NOT NULL, `index` VARCHAR(78) NOT NULL, round_address VARCHAR(42) NOT NULL, status VARCHAR(16) NOT NULL, vote_count INTEGER NOT NULL, unique_contributor_count INTEGER NOT NULL, metadata JSON, status_snapshots JSON, PRIMARY KEY(chain_id, round_address, `index`));
+CREATE TABLE pending_metadata (chain_id BIGINT UNSIGNED NOT NULL, project_id VARCHAR(78) NOT NULL, cid VARCHAR(255) NOT NULL, attempts INTEGER NOT NULL, last_error TEXT, PRIMARY KEY(chain_id, project_id));
+"#;
+
+/// SQL flavour the changes are rendered in.
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Dialect {
+    #[default]
+    Postgres,
+    Sqlite,
+    Mysql,
+}
+
+/// Weights of the texts of a search document, most important first.
+const SEARCH_WEIGHTS: [char; 4] = ['A', 'B', 'C', 'D'];
+
+impl Dialect {
+    pub fn schema(&self) -> &'static str {
+        match self {
+            Dialect::Postgres => DB_SCHEMA,
+            Dialect::Sqlite => SQLITE_SCHEMA,
+            Dialect::Mysql => MYSQL_SCHEMA,
+        }
+    }
+
+    fn build(&self, statement: &impl QueryStatementWriter) -> String {
+        match self {
+            Dialect::Postgres => statement.to_string(PostgresQueryBuilder),
+            Dialect::Sqlite => statement.to_string(SqliteQueryBuilder),
+            Dialect::Mysql => statement.to_string(MysqlQueryBuilder),
+        }
+    }
+
+    fn value(&self, value: &Value) -> SimpleExpr {
+        match (self, value) {
+            (_, Value::Null) => Expr::value(Option::<String>::None),
+            (_, Value::Integer(value)) => (*value).into(),
+            (Dialect::Postgres, Value::Numeric(value)) => Expr::cust(value.to_string()),
+            (Dialect::Sqlite | Dialect::Mysql, Value::Numeric(value)) => value.to_string().into(),
+            (_, Value::Text(value) | Value::Json(value)) => value.as_str().into(),
+            (Dialect::Postgres, Value::Search(texts)) => Expr::cust_with_values(
+                texts
+                    .iter()
+                    .enumerate()
+                    .map(|(position, _)| {
+                        format!(
+                            "setweight(to_tsvector('simple', ${}), '{}')",
+                            position + 1,
+                            SEARCH_WEIGHTS[position.min(SEARCH_WEIGHTS.len() - 1)]
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" || "),
+                texts.iter().map(String::as_str),
+            ),
+            // Indexed by a FULLTEXT index in MySQL
+            (Dialect::Sqlite | Dialect::Mysql, Value::Search(texts)) => texts.join("\n").into(),
+        }
+    }
+
+    fn values(&self, row: &Row) -> Vec<(DynIden, SimpleExpr)> {
+        row.iter()
+            .map(|(column, value)| (column.clone(), self.value(value)))
+            .collect()
+    }
+
+    fn conditions(&self, key: &Row) -> Vec<SimpleExpr> {
+        key.iter()
+            .map(|(column, value)| Expr::col(column.clone()).eq(self.value(value)))
+            .collect()
+    }
+
+    fn add_to_list(&self, column: &DynIden, item: &str) -> SimpleExpr {
+        match self {
+            Dialect::Postgres => Expr::col(column.clone())
+                .binary(BinOper::Custom("||"), serde_json::json!([item]).to_string()),
+            Dialect::Sqlite => Func::cust(Alias::new("json_insert"))
+                .args([
+                    Expr::col(column.clone()).into(),
+                    "$[#]".into(),
+                    SimpleExpr::from(item),
+                ])
+                .into(),
+            Dialect::Mysql => Func::cust(Alias::new("JSON_ARRAY_APPEND"))
+                .args([
+                    Expr::col(column.clone()).into(),
+                    "$".into(),
+                    SimpleExpr::from(item),
+                ])
+                .into(),
+        }
+    }
+
+    fn remove_from_list(&self, column: &DynIden, item: &str) -> SimpleExpr {
+        match self {
+            Dialect::Postgres => Expr::col(column.clone()).binary(BinOper::Sub, item),
+            Dialect::Sqlite => Expr::cust_with_values(
+                format!(
+                    r#"(SELECT json_group_array(value) FROM json_each("{}") WHERE value <> ?)"#,
+                    column.to_string()
+                ),
+                [item],
+            ),
+            // Only removes the first occurrence, which is enough for owners
+            // since the registry never lists an owner twice.
+            Dialect::Mysql => Expr::cust_with_values(
+                format!(
+                    "IF(JSON_CONTAINS(`{column}`, JSON_QUOTE(?)), JSON_REMOVE(`{column}`, JSON_UNQUOTE(JSON_SEARCH(`{column}`, 'one', ?))), `{column}`)",
+                    column = column.to_string()
+                ),
+                [item, item],
+            ),
+        }
+    }
+
+    fn update(&self, table: &DynIden, key: &Row, values: Vec<(DynIden, SimpleExpr)>) -> String {
+        let mut update = Query::update();
+        update.table(table.clone()).values(values);
+        for condition in self.conditions(key) {
+            update.and_where(condition);
+        }
+        self.build(&update)
+    }
+
+    pub fn render(&self, change: &Change) -> String {
+        match change {
+            Change::Insert { table, values } => self.build(
+                Query::insert()
+                    .into_table(table.clone())
+                    .columns(values.iter().map(|(column, _)| column.clone()))
+                    .values_panic(values.iter().map(|(_, value)| self.value(value))),
+            ),
+            Change::Update { table, key, values } => self.update(table, key, self.values(values)),
+            Change::Upsert { table, key, values } => self.build(
+                Query::insert()
+                    .into_table(table.clone())
+                    .columns(key.iter().chain(values).map(|(column, _)| column.clone()))
+                    .values_panic(key.iter().chain(values).map(|(_, value)| self.value(value)))
+                    .on_conflict(
+                        OnConflict::columns(key.iter().map(|(column, _)| column.clone()))
+                            .update_columns(values.iter().map(|(column, _)| column.clone()))
+                            .to_owned(),
+                    ),
+            ),
+            Change::Delete { table, key } => {
+                let mut delete = Query::delete();
+                delete.from_table(table.clone());
+                for condition in self.conditions(key) {
+                    delete.and_where(condition);
+                }
+                self.build(&delete)
+            }
+            Change::AddToList {
+                table,
+                key,
+                column,
+                item,
+            } => self.update(
+                table,
+                key,
+                vec![(column.clone(), self.add_to_list(column, item))],
+            ),
+            Change::RemoveFromList {
+                table,
+                key,
+                column,
+                item,
+            } => self.update(
+                table,
+                key,
+                vec![(column.clone(), self.remove_from_list(column, item))],
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handling::{
+        event_to_changeset_with_metadata, Event, EventPayload, MetaPtr, MetadataError,
+    };
+    use crate::test_helpers::event;
+    use ethers::types::{Address, H256, U256};
+    use rusqlite::Connection;
+
+    fn apply(
+        connection: &Connection,
+        event: Event,
+        metadata: Option<Result<String, MetadataError>>,
+    ) {
+        connection
+            .execute_batch(
+                &event_to_changeset_with_metadata(&event, metadata).to_sql(Dialect::Sqlite),
+            )
+            .unwrap();
+    }
+
+    /// One event of each type, with the metadata its changeset is built from.
+    fn sample_events() -> Vec<(Event, Option<Result<String, MetadataError>>)> {
+        let project_id = U256::from(123);
+        let owner = Address::from_low_u64_be(0xa);
+        let metadata_updated = || {
+            event(
+                1,
+                EventPayload::MetadataUpdated {
+                    project_id,
+                    meta_ptr: MetaPtr {
+                        pointer: "bafkrei".to_string(),
+                    },
+                },
+            )
+        };
+        vec![
+            (event(0, EventPayload::ProjectCreated { project_id }), None),
+            (
+                metadata_updated(),
+                Some(Ok(
+                    r#"{"title":"It's","description":"Bar","credentials":{}}"#.to_string(),
+                )),
+            ),
+            (
+                metadata_updated(),
+                Some(Err(MetadataError::Fetch("timed out".to_string()))),
+            ),
+            (
+                event(2, EventPayload::OwnerAdded { project_id, owner }),
+                None,
+            ),
+            (
+                event(3, EventPayload::OwnerRemoved { project_id, owner }),
+                None,
+            ),
+            (
+                event(
+                    4,
+                    EventPayload::RoundCreated {
+                        round_address: Address::from_low_u64_be(0x456),
+                    },
+                ),
+                None,
+            ),
+            (
+                event(
+                    5,
+                    EventPayload::NewProjectApplication {
+                        project_id: H256::from_low_u64_be(0x789),
+                        application_index: U256::zero(),
+                    },
+                ),
+                None,
+            ),
+        ]
+    }
+
+    fn render_sample_events(dialect: Dialect) -> String {
+        sample_events()
+            .into_iter()
+            .map(|(event, metadata)| {
+                event_to_changeset_with_metadata(&event, metadata).to_sql(dialect) + ";\n"
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_render_postgres() {
+        insta::assert_snapshot!(render_sample_events(Dialect::Postgres));
+    }
+
+    #[test]
+    fn test_render_sqlite() {
+        insta::assert_snapshot!(render_sample_events(Dialect::Sqlite));
+    }
+
+    #[test]
+    fn test_render_mysql() {
+        insta::assert_snapshot!(render_sample_events(Dialect::Mysql));
+    }
+
+    #[test]
+    fn test_mysql_schema_holds_uint256() {
+        // DECIMAL is limited to 65 digits in MySQL and MariaDB
+        assert!(!MYSQL_SCHEMA.contains("DECIMAL"));
+        assert!(MYSQL_SCHEMA.contains("project_id VARCHAR(78)"));
+        assert_eq!(U256::MAX.to_string().len(), 78);
+    }
+
+    #[test]
+    fn test_sqlite() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(SQLITE_SCHEMA).unwrap();
+        let project_id = U256::from(2).pow(U256::from(128));
+        let owner = |owner| {
+            event(
+                1,
+                EventPayload::OwnerAdded {
+                    project_id,
+                    owner: Address::from_low_u64_be(owner),
+                },
+            )
+        };
+
+        apply(
+            &connection,
+            event(0, EventPayload::ProjectCreated { project_id }),
+            None,
+        );
+        apply(&connection, owner(0xa), None);
+        apply(&connection, owner(0xb), None);
+        apply(&connection, owner(0xa), None);
+        apply(
+            &connection,
+            event(
+                2,
+                EventPayload::OwnerRemoved {
+                    project_id,
+                    owner: Address::from_low_u64_be(0xa),
+                },
+            ),
+            None,
+        );
+        let metadata_updated = || {
+            event(
+                3,
+                EventPayload::MetadataUpdated {
+                    project_id,
+                    meta_ptr: MetaPtr {
+                        pointer: "cid".to_string(),
+                    },
+                },
+            )
+        };
+        apply(
+            &connection,
+            metadata_updated(),
+            Some(Err(MetadataError::Fetch("timed out".to_string()))),
+        );
+        apply(
+            &connection,
+            metadata_updated(),
+            Some(Err(MetadataError::Fetch("timed out".to_string()))),
+        );
+        assert_eq!(
+            connection
+                .query_row(
+                    "SELECT project_id, cid, attempts FROM pending_metadata",
+                    [],
+                    |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, String>(1)?,
+                            row.get::<_, i64>(2)?,
+                        ))
+                    }
+                )
+                .unwrap(),
+            (project_id.to_string(), "cid".to_string(), 1)
+        );
+        apply(
+            &connection,
+            metadata_updated(),
+            Some(Ok(r#"{"title":"Foo","description":"Bar"}"#.to_string())),
+        );
+        apply(
+            &connection,
+            event(
+                4,
+                EventPayload::RoundCreated {
+                    round_address: Address::from_low_u64_be(0x456),
+                },
+            ),
+            None,
+        );
+        apply(
+            &connection,
+            event(
+                5,
+                EventPayload::NewProjectApplication {
+                    project_id: H256::from_low_u64_be(0x789),
+                    application_index: U256::zero(),
+                },
+            ),
+            None,
+        );
+
+        let project = connection
+            .query_row(
+                "SELECT project_id, owners, metadata_status, json_extract(metadata, '$.title'), title, search FROM project",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, String>(4)?,
+                        row.get::<_, String>(5)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            project,
+            (
+                "340282366920938463463374607431768211456".to_string(),
+                r#"["0x000000000000000000000000000000000000000b"]"#.to_string(),
+                "ok".to_string(),
+                "Foo".to_string(),
+                "Foo".to_string(),
+                "Foo\nBar".to_string(),
+            )
+        );
+        let pending: i64 = connection
+            .query_row("SELECT COUNT(*) FROM pending_metadata", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(pending, 0);
+        let round: String = connection
+            .query_row("SELECT round_address FROM round", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(round, "0x0000000000000000000000000000000000000456");
+        // Rounds are keyed by address, as in the store
+        let round_created = event(
+            6,
+            EventPayload::RoundCreated {
+                round_address: Address::from_low_u64_be(0x456),
+            },
+        );
+        assert!(connection
+            .execute_batch(
+                &event_to_changeset_with_metadata(&round_created, None).to_sql(Dialect::Sqlite)
+            )
+            .is_err());
+        let application: (String, String, String) = connection
+            .query_row(
+                "SELECT project_id, \"index\", status FROM application",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            application,
+            (
+                "0x0000000000000000000000000000000000000000000000000000000000000789".to_string(),
+                "0".to_string(),
+                "PENDING".to_string()
+            )
+        );
+    }
+}
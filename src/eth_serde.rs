@@ -0,0 +1,124 @@
+//! Serde helpers for Ethereum types as they appear in gs-log JSON.
+
+/// Addresses are serialized in EIP-55 checksummed form. On deserialization,
+/// all-lowercase and all-uppercase addresses are accepted as-is, while
+/// mixed-case addresses must carry a valid checksum.
+pub mod checksummed_address {
+    use ethers::types::Address;
+    use ethers::utils::to_checksum;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(address: &Address, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&to_checksum(address, None))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Address, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        parse(&value).map_err(de::Error::custom)
+    }
+
+    pub fn parse(value: &str) -> Result<Address, String> {
+        let hex = value
+            .strip_prefix("0x")
+            .ok_or_else(|| format!("address {} is missing the 0x prefix", value))?;
+        if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("{} is not a 20-byte hex address", value));
+        }
+
+        let address: Address = value.parse().map_err(|err| format!("{}", err))?;
+        let is_mixed_case = hex.chars().any(|c| c.is_ascii_lowercase())
+            && hex.chars().any(|c| c.is_ascii_uppercase());
+        if is_mixed_case && to_checksum(&address, None) != value {
+            return Err(format!("address {} has an invalid checksum", value));
+        }
+
+        Ok(address)
+    }
+}
+
+/// Unsigned 256-bit integers are serialized as 0x-prefixed hex strings. On
+/// deserialization, JSON numbers, decimal strings and hex strings are accepted.
+pub mod u256 {
+    use ethers::types::U256;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NumberOrString {
+        Number(u64),
+        String(String),
+    }
+
+    pub fn serialize<S: Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        value.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        match NumberOrString::deserialize(deserializer)? {
+            NumberOrString::Number(number) => Ok(U256::from(number)),
+            NumberOrString::String(string) => parse(&string).map_err(de::Error::custom),
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<U256, String> {
+        let parsed = match value.strip_prefix("0x") {
+            Some(hex) if !hex.is_empty() => U256::from_str_radix(hex, 16).ok(),
+            Some(_) => None,
+            None => U256::from_dec_str(value).ok(),
+        };
+        parsed.ok_or_else(|| format!("{} is not a valid uint256", value))
+    }
+}
+
+/// Quantities such as block numbers and log indexes, accepted as JSON
+/// numbers, decimal strings or hex strings (as returned by JSON-RPC).
+pub mod quantity {
+    use serde::{de, Deserializer};
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        let value = super::u256::deserialize(deserializer)?;
+        if value.bits() > 64 {
+            return Err(de::Error::custom(format!(
+                "{} does not fit in 64 bits",
+                value
+            )));
+        }
+        Ok(value.as_u64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{Address, U256};
+
+    #[test]
+    fn test_parse_address() {
+        let checksummed = "0x6294bed5B884Ae18bf737793Ef9415069Bf4bc11";
+        let expected: Address = checksummed.to_lowercase().parse().unwrap();
+
+        assert_eq!(checksummed_address::parse(checksummed), Ok(expected));
+        assert_eq!(
+            checksummed_address::parse(&checksummed.to_lowercase()),
+            Ok(expected)
+        );
+        assert!(checksummed_address::parse("0x6294bed5b884Ae18bf737793Ef9415069Bf4bc11").is_err());
+        assert!(checksummed_address::parse("0x123").is_err());
+        assert!(checksummed_address::parse("6294bed5b884ae18bf737793ef9415069bf4bc11").is_err());
+    }
+
+    #[test]
+    fn test_parse_u256() {
+        assert_eq!(u256::parse("0x00"), Ok(U256::zero()));
+        assert_eq!(u256::parse("0x7b"), Ok(U256::from(123)));
+        assert_eq!(u256::parse("123"), Ok(U256::from(123)));
+        assert_eq!(
+            u256::parse(
+                "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+            ),
+            Ok(U256::MAX)
+        );
+        assert!(u256::parse("0x").is_err());
+        assert!(u256::parse("proj-123").is_err());
+    }
+}
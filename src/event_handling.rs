@@ -1,27 +1,60 @@
 use std::pin::Pin;
 
-use sea_query::{Expr, Iden, PostgresQueryBuilder, Query};
+use ethers::types::{Address, H256, U256};
+use sea_query::{Iden, IntoIden};
 use serde::{Deserialize, Serialize};
 use serde_json::{from_str, to_string};
 use tokio_postgres::{Client, Error, NoTls, Transaction};
 
+use crate::changes::{Change, Row, Value};
+use crate::dialect::Dialect;
+use crate::eth_serde::{checksummed_address, u256};
+use crate::project_metadata::ProjectMetadata;
+
 // TODO add round table
 pub const DB_SCHEMA: &str = r#"
-CREATE TABLE project (chain_id INTEGER NOT NULL, project_id VARCHAR NOT NULL, created_at_block BIGINT NOT NULL, metadata JSONB, PRIMARY KEY(chain_id, project_id));
-CREATE TABLE round (chain_id INTEGER NOT NULL, round_address VARCHAR NOT NULL, created_at_block BIGINT NOT NULL);
+CREATE TABLE project (chain_id BIGINT NOT NULL, project_id NUMERIC NOT NULL, created_at_block BIGINT NOT NULL, metadata JSONB, metadata_status VARCHAR, metadata_error TEXT, title TEXT, description TEXT, website TEXT, logo_img VARCHAR, banner_img VARCHAR, project_twitter VARCHAR, user_github VARCHAR, project_github VARCHAR, credentials JSONB, search TSVECTOR, owners JSONB NOT NULL DEFAULT '[]', PRIMARY KEY(chain_id, project_id));
+CREATE INDEX project_search ON project USING GIN (search);
+CREATE TABLE round (chain_id BIGINT NOT NULL, round_address VARCHAR NOT NULL, created_at_block BIGINT NOT NULL, PRIMARY KEY(chain_id, round_address));
+CREATE TABLE application (chain_id BIGINT NOT NULL, created_at_block BIGINT NOT NULL, status_updated_at_block BIGINT NOT NULL, project_id VARCHAR NOT NULL, "index" NUMERIC NOT NULL, round_address VARCHAR NOT NULL, status VARCHAR NOT NULL, vote_count INTEGER NOT NULL, unique_contributor_count INTEGER NOT NULL, metadata JSONB, status_snapshots JSONB, PRIMARY KEY(chain_id, round_address, "index"));
+CREATE TABLE pending_metadata (chain_id BIGINT NOT NULL, project_id NUMERIC NOT NULL, cid VARCHAR NOT NULL, attempts INTEGER NOT NULL, last_error TEXT, PRIMARY KEY(chain_id, project_id));
 "#;
 
 #[derive(Iden)]
+#[allow(clippy::enum_variant_names)]
 enum Project {
     Table,
     ChainId,
     ProjectId,
     CreatedAtBlock,
     Metadata,
+    MetadataStatus,
+    MetadataError,
+    Title,
+    Description,
+    Website,
+    LogoImg,
+    BannerImg,
+    ProjectTwitter,
+    UserGithub,
+    ProjectGithub,
+    Credentials,
+    Search,
     Owners,
 }
 
 #[derive(Iden)]
+pub enum PendingMetadata {
+    Table,
+    ChainId,
+    ProjectId,
+    Cid,
+    Attempts,
+    LastError,
+}
+
+#[derive(Iden)]
+#[allow(clippy::enum_variant_names)]
 enum Round {
     Table,
     ChainId,
@@ -48,11 +81,11 @@ enum Application {
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Event {
-    // TODO use stricter types
-    pub chain_id: i32,
-    pub address: String,
-    pub block_number: i32,
-    pub log_index: i32,
+    pub chain_id: u64,
+    #[serde(with = "checksummed_address")]
+    pub address: Address,
+    pub block_number: u64,
+    pub log_index: u64,
     pub data: EventPayload,
 }
 
@@ -60,35 +93,37 @@ pub struct Event {
 #[serde(tag = "type")]
 pub enum EventPayload {
     ProjectCreated {
-        #[serde(rename = "projectID")]
-        project_id: String,
+        #[serde(rename = "projectID", with = "u256")]
+        project_id: U256,
     },
     MetadataUpdated {
-        #[serde(rename = "projectID")]
-        project_id: String,
+        #[serde(rename = "projectID", with = "u256")]
+        project_id: U256,
         #[serde(rename = "metaPtr")]
         meta_ptr: MetaPtr,
     },
     OwnerAdded {
-        #[serde(rename = "projectID")]
-        project_id: String,
-        owner: String,
+        #[serde(rename = "projectID", with = "u256")]
+        project_id: U256,
+        #[serde(with = "checksummed_address")]
+        owner: Address,
     },
     OwnerRemoved {
-        #[serde(rename = "projectID")]
-        project_id: String,
-        owner: String,
+        #[serde(rename = "projectID", with = "u256")]
+        project_id: U256,
+        #[serde(with = "checksummed_address")]
+        owner: Address,
     },
     RoundCreated {
-        #[serde(rename = "roundAddress")]
-        round_address: String,
+        #[serde(rename = "roundAddress", with = "checksummed_address")]
+        round_address: Address,
     },
     NewProjectApplication {
         // TODO verify if the variant "project" should be supported
         #[serde(rename = "projectID")]
-        project_id: String,
-        #[serde(rename = "applicationIndex")]
-        application_index: i32,
+        project_id: H256,
+        #[serde(rename = "applicationIndex", with = "u256")]
+        application_index: U256,
     },
 }
 
@@ -97,112 +132,344 @@ pub struct MetaPtr {
     pub pointer: String,
 }
 
+/// Why the metadata of an event could not be stored.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataError {
+    /// The document could not be fetched, for now at least
+    Fetch(String),
+    /// The document is known not to match its CID, or cannot be decoded.
+    /// Fetching it again cannot help.
+    InvalidContent(String),
+    /// The document is not valid JSON
+    InvalidJson(String),
+}
+
+impl std::fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetadataError::Fetch(err) => write!(f, "cannot fetch metadata: {}", err),
+            MetadataError::InvalidContent(err) => write!(f, "invalid metadata content: {}", err),
+            MetadataError::InvalidJson(err) => write!(f, "metadata is not valid JSON: {}", err),
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct ChangeSet {
-    pub sql: String,
+    pub changes: Vec<Change>,
+    /// Contracts whose events should be indexed from now on, e.g. a newly
+    /// created round.
+    pub discovered_contracts: Vec<Address>,
+}
+
+impl ChangeSet {
+    fn new(changes: Vec<Change>) -> Self {
+        ChangeSet {
+            changes,
+            ..Default::default()
+        }
+    }
+
+    pub fn to_sql(&self, dialect: Dialect) -> String {
+        changes_to_sql(&self.changes, dialect)
+    }
+}
+
+pub fn changes_to_sql(changes: &[Change], dialect: Dialect) -> String {
+    changes
+        .iter()
+        .map(|change| dialect.render(change))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+// Block numbers and chain ids are integers, uint256 values numeric, and
+// addresses and hashes lowercase 0x-prefixed hex strings.
+
+fn hex(value: impl std::fmt::LowerHex) -> String {
+    format!("{:#x}", value)
+}
+
+fn project_key(chain_id: u64, project_id: &U256) -> Row {
+    vec![
+        (Project::ChainId.into_iden(), chain_id.into()),
+        (Project::ProjectId.into_iden(), (*project_id).into()),
+    ]
+}
+
+/// IPFS document the changeset of `event` is built from, if any.
+pub fn metadata_pointer(event: &Event) -> Option<&str> {
+    match &event.data {
+        EventPayload::MetadataUpdated { meta_ptr, .. } => Some(&meta_ptr.pointer),
+        _ => None,
+    }
+}
+
+/// Contracts whose events should be indexed because of `event`. Known
+/// without building the changeset, so that sources can be told about them
+/// as early as possible.
+pub fn discovered_contracts(event: &Event) -> Vec<Address> {
+    match &event.data {
+        EventPayload::RoundCreated { round_address } => vec![*round_address],
+        _ => vec![],
+    }
+}
+
+/// `project.metadata_status`: whether the metadata column holds the
+/// document of the latest `MetadataUpdated`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetadataStatus {
+    Ok,
+    /// Could not be fetched yet, queued in `pending_metadata`
+    Pending,
+    /// Fetched, but not usable; not retried. The metadata column still
+    /// holds documents that are JSON but do not match the project schema.
+    Invalid,
+}
+
+impl MetadataStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MetadataStatus::Ok => "ok",
+            MetadataStatus::Pending => "pending",
+            MetadataStatus::Invalid => "invalid",
+        }
+    }
+}
+
+/// Changes storing document `cid` as the metadata of a project, along with
+/// the fields of the project schema. Documents that cannot be used are
+/// recorded as errors, leaving no metadata rather than content nobody can
+/// trust; those that could not be fetched are queued for `retry-metadata`.
+pub fn project_metadata_changes(
+    chain_id: u64,
+    project_id: &U256,
+    cid: &str,
+    metadata: Result<String, MetadataError>,
+) -> Vec<Change> {
+    let metadata = metadata.and_then(|metadata| match from_str::<serde_json::Value>(&metadata) {
+        Ok(_) => Ok(metadata),
+        Err(err) => Err(MetadataError::InvalidJson(err.to_string())),
+    });
+    let (metadata, fields, status, error) = match metadata {
+        Ok(metadata) => match ProjectMetadata::parse(&metadata) {
+            Ok(fields) => (Some(metadata), Some(fields), MetadataStatus::Ok, None),
+            Err(err) => (
+                Some(metadata),
+                None,
+                MetadataStatus::Invalid,
+                Some(format!(
+                    "metadata does not match the project schema: {}",
+                    err
+                )),
+            ),
+        },
+        Err(err @ (MetadataError::InvalidJson(_) | MetadataError::InvalidContent(_))) => {
+            (None, None, MetadataStatus::Invalid, Some(err.to_string()))
+        }
+        Err(err @ MetadataError::Fetch(_)) => {
+            (None, None, MetadataStatus::Pending, Some(err.to_string()))
+        }
+    };
+
+    // Typed columns are NULL unless the document matches the schema.
+    let field = |get: fn(&ProjectMetadata) -> Option<String>| -> Value {
+        fields.as_ref().and_then(get).into()
+    };
+    // Titles weigh more than descriptions when ranking search results.
+    let search = match &fields {
+        Some(fields) => Value::Search(vec![fields.title.clone(), fields.description.clone()]),
+        None => Value::Null,
+    };
+
+    let update = Change::Update {
+        table: Project::Table.into_iden(),
+        key: project_key(chain_id, project_id),
+        values: vec![
+            (
+                Project::Metadata.into_iden(),
+                metadata.map_or(Value::Null, Value::Json),
+            ),
+            (Project::MetadataStatus.into_iden(), status.as_str().into()),
+            (Project::MetadataError.into_iden(), error.clone().into()),
+            (
+                Project::Title.into_iden(),
+                field(|fields| Some(fields.title.clone())),
+            ),
+            (
+                Project::Description.into_iden(),
+                field(|fields| Some(fields.description.clone())),
+            ),
+            (
+                Project::Website.into_iden(),
+                field(|fields| fields.website.clone()),
+            ),
+            (
+                Project::LogoImg.into_iden(),
+                field(|fields| fields.logo_img.clone()),
+            ),
+            (
+                Project::BannerImg.into_iden(),
+                field(|fields| fields.banner_img.clone()),
+            ),
+            (
+                Project::ProjectTwitter.into_iden(),
+                field(|fields| fields.project_twitter.clone()),
+            ),
+            (
+                Project::UserGithub.into_iden(),
+                field(|fields| fields.user_github.clone()),
+            ),
+            (
+                Project::ProjectGithub.into_iden(),
+                field(|fields| fields.project_github.clone()),
+            ),
+            (
+                Project::Credentials.into_iden(),
+                fields
+                    .as_ref()
+                    .and_then(|fields| to_string(&fields.credentials).ok())
+                    .map_or(Value::Null, Value::Json),
+            ),
+            (Project::Search.into_iden(), search),
+        ],
+    };
+    // A newer document supersedes any pending one.
+    let pending_key = vec![
+        (PendingMetadata::ChainId.into_iden(), chain_id.into()),
+        (PendingMetadata::ProjectId.into_iden(), (*project_id).into()),
+    ];
+    let queue = match status {
+        MetadataStatus::Pending => Change::Upsert {
+            table: PendingMetadata::Table.into_iden(),
+            key: pending_key,
+            values: vec![
+                (PendingMetadata::Cid.into_iden(), cid.into()),
+                (PendingMetadata::Attempts.into_iden(), 1.into()),
+                (PendingMetadata::LastError.into_iden(), error.into()),
+            ],
+        },
+        _ => Change::Delete {
+            table: PendingMetadata::Table.into_iden(),
+            key: pending_key,
+        },
+    };
+    vec![update, queue]
+}
+
+/// `project_metadata_changes` as Postgres statements.
+pub fn project_metadata_sql(
+    chain_id: u64,
+    project_id: &U256,
+    cid: &str,
+    metadata: Result<String, MetadataError>,
+) -> String {
+    changes_to_sql(
+        &project_metadata_changes(chain_id, project_id, cid, metadata),
+        Dialect::Postgres,
+    )
 }
 
 pub async fn event_to_changeset(
     event: &Event,
-    ipfs_getter: impl Fn(String) -> Pin<Box<dyn futures::Future<Output = String> + Send>>,
+    ipfs_getter: impl Fn(
+        String,
+    )
+        -> Pin<Box<dyn futures::Future<Output = Result<String, MetadataError>> + Send>>,
+) -> ChangeSet {
+    let metadata = match metadata_pointer(event) {
+        Some(pointer) => Some(ipfs_getter(pointer.to_string()).await),
+        None => None,
+    };
+    event_to_changeset_with_metadata(event, metadata)
+}
+
+/// Builds the changeset of `event` given the document behind
+/// `metadata_pointer(event)`, fetched beforehand.
+pub fn event_to_changeset_with_metadata(
+    event: &Event,
+    metadata: Option<Result<String, MetadataError>>,
 ) -> ChangeSet {
     match &event.data {
-        EventPayload::ProjectCreated { project_id } => ChangeSet {
-            sql: Query::insert()
-                .into_table(Project::Table)
-                .columns([
-                    Project::ChainId,
-                    Project::ProjectId,
-                    Project::CreatedAtBlock,
-                ])
-                .values_panic([
-                    event.chain_id.into(),
-                    project_id.into(),
+        EventPayload::ProjectCreated { project_id } => ChangeSet::new(vec![Change::Insert {
+            table: Project::Table.into_iden(),
+            values: vec![
+                (Project::ChainId.into_iden(), event.chain_id.into()),
+                (Project::ProjectId.into_iden(), (*project_id).into()),
+                (
+                    Project::CreatedAtBlock.into_iden(),
                     event.block_number.into(),
-                ])
-                .to_string(PostgresQueryBuilder),
-        },
+                ),
+            ],
+        }]),
 
         EventPayload::MetadataUpdated {
+            project_id,
             meta_ptr,
+        } => ChangeSet::new(project_metadata_changes(
+            event.chain_id,
             project_id,
-        } => {
-            let metadata = ipfs_getter(meta_ptr.pointer.clone()).await;
-            ChangeSet {
-                sql: Query::update()
-                    .table(Project::Table)
-                    .values([(Project::Metadata, metadata.into())])
-                    .and_where(Expr::col(Project::ChainId).eq(event.chain_id))
-                    .and_where(Expr::col(Project::ProjectId).eq(project_id))
-                    .to_string(PostgresQueryBuilder),
-            }
-        }
+            &meta_ptr.pointer,
+            metadata.expect("metadata of MetadataUpdated must be fetched"),
+        )),
 
-        EventPayload::OwnerAdded { project_id, owner } => {
-            ChangeSet {
-                // TODO build SQL safely
-                sql: format!(
-                    r#"UPDATE "project" SET "owners" = ("owners" || '["{}"]') WHERE "chain_id" = {} AND "project_id" = '{}'"#,
-                    owner, event.chain_id, project_id
-                ),
-            }
-        }
+        EventPayload::OwnerAdded { project_id, owner } => ChangeSet::new(vec![Change::AddToList {
+            table: Project::Table.into_iden(),
+            key: project_key(event.chain_id, project_id),
+            column: Project::Owners.into_iden(),
+            item: hex(owner),
+        }]),
 
         EventPayload::OwnerRemoved { project_id, owner } => {
-            ChangeSet {
-                // TODO build SQL safely
-                sql: format!(
-                    r#"UPDATE "project" SET "owners" = ("owners" - '{}') WHERE "chain_id" = {} AND "project_id" = '{}'"#,
-                    owner, event.chain_id, project_id
-                ),
-            }
+            ChangeSet::new(vec![Change::RemoveFromList {
+                table: Project::Table.into_iden(),
+                key: project_key(event.chain_id, project_id),
+                column: Project::Owners.into_iden(),
+                item: hex(owner),
+            }])
         }
 
         EventPayload::RoundCreated { round_address } => ChangeSet {
-            sql: Query::insert()
-                .into_table(Round::Table)
-                .columns([Round::ChainId, Round::RoundAddress, Round::CreatedAtBlock])
-                .values_panic([
-                    event.chain_id.into(),
-                    round_address.into(),
-                    event.block_number.into(),
-                ])
-                .to_string(PostgresQueryBuilder),
+            changes: vec![Change::Insert {
+                table: Round::Table.into_iden(),
+                values: vec![
+                    (Round::ChainId.into_iden(), event.chain_id.into()),
+                    (Round::RoundAddress.into_iden(), hex(round_address).into()),
+                    (Round::CreatedAtBlock.into_iden(), event.block_number.into()),
+                ],
+            }],
+            discovered_contracts: discovered_contracts(event),
         },
 
         EventPayload::NewProjectApplication {
             project_id,
             application_index,
-        } => ChangeSet {
-            sql: Query::insert()
-                .into_table(Application::Table)
-                .columns([
-                    Application::ChainId,
-                    Application::CreatedAtBlock,
-                    Application::StatusUpdatedAtBlock,
-                    Application::ProjectId,
-                    Application::Index,
-                    Application::RoundAddress,
-                    Application::Status,
-                    Application::VoteCount,
-                    Application::UniqueContributorCount,
-                    //Application::Metadata,
-                    //Application::StatusSnapshots,
-                ])
-                .values_panic([
-                    event.chain_id.into(),
+        } => ChangeSet::new(vec![Change::Insert {
+            table: Application::Table.into_iden(),
+            values: vec![
+                (Application::ChainId.into_iden(), event.chain_id.into()),
+                (
+                    Application::CreatedAtBlock.into_iden(),
                     event.block_number.into(),
+                ),
+                (
+                    Application::StatusUpdatedAtBlock.into_iden(),
                     event.block_number.into(),
-                    project_id.into(),
-                    application_index.to_owned().into(),
-                    event.address.to_owned().into(),
-                    "PENDING".into(),
-                    0.into(),
-                    0.into(),
-                    //None,
-                    //TODO should be [{status: "PENDING",statusUpdatedAtBlock: event.blockNumber}]
-                ])
-                .to_string(PostgresQueryBuilder),
-        },
+                ),
+                (Application::ProjectId.into_iden(), hex(project_id).into()),
+                (Application::Index.into_iden(), (*application_index).into()),
+                (
+                    Application::RoundAddress.into_iden(),
+                    hex(event.address).into(),
+                ),
+                (Application::Status.into_iden(), "PENDING".into()),
+                (Application::VoteCount.into_iden(), 0.into()),
+                (Application::UniqueContributorCount.into_iden(), 0.into()),
+                //(Application::Metadata.into_iden(), Value::Null),
+                //TODO should be [{status: "PENDING",statusUpdatedAtBlock: event.blockNumber}]
+                //(Application::StatusSnapshots.into_iden(), Value::Null),
+            ],
+        }]),
     }
 }
 
@@ -210,33 +477,54 @@ pub async fn event_to_changeset(
 mod tests {
     use super::*;
 
-    fn dummy_ipfs_getter(_url: String) -> Pin<Box<dyn futures::Future<Output = String> + Send>> {
-        Box::pin(async move { r#"{ "foo": "bar" }"#.to_string() })
+    fn dummy_ipfs_getter(
+        _url: String,
+    ) -> Pin<Box<dyn futures::Future<Output = Result<String, MetadataError>> + Send>> {
+        Box::pin(async move { Ok(r#"{ "title": "Foo", "description": "Bar" }"#.to_string()) })
     }
 
     #[test]
     fn test_parse_event_json() {
         let event_data = r#"{"chainId":58008,"data":{"type":"ProjectCreated","projectID":"0x00","owner":"0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"},"address":"0x6294bed5B884Ae18bf737793Ef9415069Bf4bc11","signature":"ProjectCreated(uint256,address)","transactionHash":"0xdeae76e835f3d33f09c6e23b6ce5a831a6f8d314f4ac1823369f34b3bba0e0df","blockNumber":1070024,"logIndex":0}"#;
-        let event: Event = from_str(&event_data).unwrap();
+        let event: Event = from_str(event_data).unwrap();
         assert_eq!(event.chain_id, 58008);
         assert!(matches!(event.data, EventPayload::ProjectCreated { .. }));
     }
 
+    #[test]
+    fn test_parse_event_json_with_large_values() {
+        let event_data = r#"{"chainId":42161,"data":{"type":"NewProjectApplication","projectID":"0xa6d2b5a4a4b2b1f4f8e1f37c2b0ae1b3c64e2d52a9b1a8ff4b1c3d4e5f60718a","applicationIndex":"340282366920938463463374607431768211456"},"address":"0x6294bed5b884ae18bf737793ef9415069bf4bc11","blockNumber":150000000000,"logIndex":3}"#;
+        let event: Event = from_str(event_data).unwrap();
+        assert_eq!(event.block_number, 150_000_000_000);
+        assert!(matches!(
+            event.data,
+            EventPayload::NewProjectApplication { application_index, .. } if application_index == U256::from(2).pow(U256::from(128))
+        ));
+    }
+
+    #[test]
+    fn test_parse_event_json_with_bad_checksum() {
+        let event_data = r#"{"chainId":1,"data":{"type":"RoundCreated","roundAddress":"0x6294BED5B884Ae18bf737793Ef9415069Bf4bc11"},"address":"0x6294bed5B884Ae18bf737793Ef9415069Bf4bc11","blockNumber":1,"logIndex":0}"#;
+        assert!(from_str::<Event>(event_data).is_err());
+    }
+
     #[tokio::test]
     async fn test_handle_project_created() {
         let event = Event {
             chain_id: 1,
-            address: "0x123".to_string(),
+            address: Address::from_low_u64_be(0x123),
             block_number: 4242,
             log_index: 1,
             data: EventPayload::ProjectCreated {
-                project_id: "proj-123".to_string(),
+                project_id: U256::from(123),
             },
         };
 
         assert_eq!(
-            event_to_changeset(&event, dummy_ipfs_getter).await.sql,
-            r#"INSERT INTO "project" ("chain_id", "project_id", "created_at_block") VALUES (1, 'proj-123', 4242)"#
+            event_to_changeset(&event, dummy_ipfs_getter)
+                .await
+                .to_sql(Dialect::Postgres),
+            r#"INSERT INTO "project" ("chain_id", "project_id", "created_at_block") VALUES (1, 123, 4242)"#
         );
     }
 
@@ -244,11 +532,11 @@ mod tests {
     async fn test_handle_metadata_updated() {
         let event = Event {
             chain_id: 1,
-            address: "0x123".to_string(),
+            address: Address::from_low_u64_be(0x123),
             block_number: 4242,
             log_index: 1,
             data: EventPayload::MetadataUpdated {
-                project_id: "proj-123".to_string(),
+                project_id: U256::from(123),
                 meta_ptr: MetaPtr {
                     pointer: "123".to_string(),
                 },
@@ -256,8 +544,55 @@ mod tests {
         };
 
         assert_eq!(
-            event_to_changeset(&event, dummy_ipfs_getter).await.sql,
-            r#"UPDATE "project" SET "metadata" = E'{ \"foo\": \"bar\" }' WHERE "chain_id" = 1 AND "project_id" = 'proj-123'"#
+            event_to_changeset(&event, dummy_ipfs_getter)
+                .await
+                .to_sql(Dialect::Postgres),
+            r#"UPDATE "project" SET "metadata" = E'{ \"title\": \"Foo\", \"description\": \"Bar\" }', "metadata_status" = 'ok', "metadata_error" = NULL, "title" = 'Foo', "description" = 'Bar', "website" = NULL, "logo_img" = NULL, "banner_img" = NULL, "project_twitter" = NULL, "user_github" = NULL, "project_github" = NULL, "credentials" = '{}', "search" = setweight(to_tsvector('simple', 'Foo'), 'A') || setweight(to_tsvector('simple', 'Bar'), 'B') WHERE "chain_id" = 1 AND "project_id" = (123); DELETE FROM "pending_metadata" WHERE "chain_id" = 1 AND "project_id" = (123)"#
+        );
+    }
+
+    #[test]
+    fn test_handle_unusable_metadata() {
+        let event = Event {
+            chain_id: 1,
+            address: Address::from_low_u64_be(0x123),
+            block_number: 4242,
+            log_index: 1,
+            data: EventPayload::MetadataUpdated {
+                project_id: U256::from(123),
+                meta_ptr: MetaPtr {
+                    pointer: "123".to_string(),
+                },
+            },
+        };
+
+        assert_eq!(
+            event_to_changeset_with_metadata(&event, Some(Ok("<html>".to_string())))
+                .to_sql(Dialect::Postgres),
+            r#"UPDATE "project" SET "metadata" = NULL, "metadata_status" = 'invalid', "metadata_error" = 'metadata is not valid JSON: expected value at line 1 column 1', "title" = NULL, "description" = NULL, "website" = NULL, "logo_img" = NULL, "banner_img" = NULL, "project_twitter" = NULL, "user_github" = NULL, "project_github" = NULL, "credentials" = NULL, "search" = NULL WHERE "chain_id" = 1 AND "project_id" = (123); DELETE FROM "pending_metadata" WHERE "chain_id" = 1 AND "project_id" = (123)"#
+        );
+        assert_eq!(
+            event_to_changeset_with_metadata(&event, Some(Ok(r#"{"title":"x"}"#.to_string())))
+                .to_sql(Dialect::Postgres),
+            r#"UPDATE "project" SET "metadata" = E'{\"title\":\"x\"}', "metadata_status" = 'invalid', "metadata_error" = 'metadata does not match the project schema: missing field `description` at line 1 column 13', "title" = NULL, "description" = NULL, "website" = NULL, "logo_img" = NULL, "banner_img" = NULL, "project_twitter" = NULL, "user_github" = NULL, "project_github" = NULL, "credentials" = NULL, "search" = NULL WHERE "chain_id" = 1 AND "project_id" = (123); DELETE FROM "pending_metadata" WHERE "chain_id" = 1 AND "project_id" = (123)"#
+        );
+        assert_eq!(
+            event_to_changeset_with_metadata(
+                &event,
+                Some(Err(MetadataError::InvalidContent(
+                    "content does not match 123".to_string()
+                )))
+            )
+            .to_sql(Dialect::Postgres),
+            r#"UPDATE "project" SET "metadata" = NULL, "metadata_status" = 'invalid', "metadata_error" = 'invalid metadata content: content does not match 123', "title" = NULL, "description" = NULL, "website" = NULL, "logo_img" = NULL, "banner_img" = NULL, "project_twitter" = NULL, "user_github" = NULL, "project_github" = NULL, "credentials" = NULL, "search" = NULL WHERE "chain_id" = 1 AND "project_id" = (123); DELETE FROM "pending_metadata" WHERE "chain_id" = 1 AND "project_id" = (123)"#
+        );
+        assert_eq!(
+            event_to_changeset_with_metadata(
+                &event,
+                Some(Err(MetadataError::Fetch("timed out".to_string())))
+            )
+            .to_sql(Dialect::Postgres),
+            r#"UPDATE "project" SET "metadata" = NULL, "metadata_status" = 'pending', "metadata_error" = 'cannot fetch metadata: timed out', "title" = NULL, "description" = NULL, "website" = NULL, "logo_img" = NULL, "banner_img" = NULL, "project_twitter" = NULL, "user_github" = NULL, "project_github" = NULL, "credentials" = NULL, "search" = NULL WHERE "chain_id" = 1 AND "project_id" = (123); INSERT INTO "pending_metadata" ("chain_id", "project_id", "cid", "attempts", "last_error") VALUES (1, 123, '123', 1, 'cannot fetch metadata: timed out') ON CONFLICT ("chain_id", "project_id") DO UPDATE SET "cid" = "excluded"."cid", "attempts" = "excluded"."attempts", "last_error" = "excluded"."last_error""#
         );
     }
 
@@ -265,18 +600,20 @@ mod tests {
     async fn test_handle_owner_added() {
         let event = Event {
             chain_id: 1,
-            address: "0x123".to_string(),
+            address: Address::from_low_u64_be(0x123),
             block_number: 4242,
             log_index: 1,
             data: EventPayload::OwnerAdded {
-                project_id: "proj-123".to_string(),
-                owner: "0x123".to_string(),
+                project_id: U256::from(123),
+                owner: Address::from_low_u64_be(0x123),
             },
         };
 
         assert_eq!(
-            event_to_changeset(&event, dummy_ipfs_getter).await.sql,
-            r#"UPDATE "project" SET "owners" = ("owners" || '["0x123"]') WHERE "chain_id" = 1 AND "project_id" = 'proj-123'"#
+            event_to_changeset(&event, dummy_ipfs_getter)
+                .await
+                .to_sql(Dialect::Postgres),
+            r#"UPDATE "project" SET "owners" = "owners" || E'[\"0x0000000000000000000000000000000000000123\"]' WHERE "chain_id" = 1 AND "project_id" = (123)"#
         );
     }
 
@@ -284,18 +621,20 @@ mod tests {
     async fn test_handle_owner_removed() {
         let event = Event {
             chain_id: 1,
-            address: "0x123".to_string(),
+            address: Address::from_low_u64_be(0x123),
             block_number: 4242,
             log_index: 1,
             data: EventPayload::OwnerRemoved {
-                project_id: "proj-123".to_string(),
-                owner: "0x123".to_string(),
+                project_id: U256::from(123),
+                owner: Address::from_low_u64_be(0x123),
             },
         };
 
         assert_eq!(
-            event_to_changeset(&event, dummy_ipfs_getter).await.sql,
-            r#"UPDATE "project" SET "owners" = ("owners" - '0x123') WHERE "chain_id" = 1 AND "project_id" = 'proj-123'"#
+            event_to_changeset(&event, dummy_ipfs_getter)
+                .await
+                .to_sql(Dialect::Postgres),
+            r#"UPDATE "project" SET "owners" = "owners" - '0x0000000000000000000000000000000000000123' WHERE "chain_id" = 1 AND "project_id" = (123)"#
         );
     }
 
@@ -303,17 +642,22 @@ mod tests {
     async fn test_handle_contract_round_created() {
         let event = Event {
             chain_id: 1,
-            address: "0x123".to_string(),
+            address: Address::from_low_u64_be(0x123),
             block_number: 4242,
             log_index: 1,
             data: EventPayload::RoundCreated {
-                round_address: "0x123".to_string(),
+                round_address: Address::from_low_u64_be(0x123),
             },
         };
 
+        let change_set = event_to_changeset(&event, dummy_ipfs_getter).await;
+        assert_eq!(
+            change_set.to_sql(Dialect::Postgres),
+            r#"INSERT INTO "round" ("chain_id", "round_address", "created_at_block") VALUES (1, '0x0000000000000000000000000000000000000123', 4242)"#
+        );
         assert_eq!(
-            event_to_changeset(&event, dummy_ipfs_getter).await.sql,
-            r#"INSERT INTO "round" ("chain_id", "round_address", "created_at_block") VALUES (1, '0x123', 4242)"#
+            change_set.discovered_contracts,
+            vec![Address::from_low_u64_be(0x123)]
         );
     }
 
@@ -321,18 +665,20 @@ mod tests {
     async fn test_handle_new_project_application() {
         let event = Event {
             chain_id: 1,
-            address: "0x123".to_string(),
+            address: Address::from_low_u64_be(0x123),
             block_number: 4242,
             log_index: 1,
             data: EventPayload::NewProjectApplication {
-                project_id: "0x456".to_string(),
-                application_index: 0,
+                project_id: H256::from_low_u64_be(0x789),
+                application_index: U256::zero(),
             },
         };
 
         assert_eq!(
-            event_to_changeset(&event, dummy_ipfs_getter).await.sql,
-            r#"INSERT INTO "application" ("chain_id", "created_at_block", "status_updated_at_block", "project_id", "index", "round_address", "status", "vote_count", "unique_contributor_count") VALUES (1, 4242, 4242, '0x456', 0, '0x123', 'PENDING', 0, 0)"#
+            event_to_changeset(&event, dummy_ipfs_getter)
+                .await
+                .to_sql(Dialect::Postgres),
+            r#"INSERT INTO "application" ("chain_id", "created_at_block", "status_updated_at_block", "project_id", "index", "round_address", "status", "vote_count", "unique_contributor_count") VALUES (1, 4242, 4242, '0x0000000000000000000000000000000000000000000000000000000000000789', 0, '0x0000000000000000000000000000000000000123', 'PENDING', 0, 0)"#
         );
     }
 }
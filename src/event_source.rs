@@ -8,7 +8,39 @@ use std::io::Write;
 use std::io::{self, BufRead, BufReader};
 use std::path::Path;
 
+use clap::ValueEnum;
+use ethers::types::{Address, Bytes, Log, H256};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::eth_serde::{checksummed_address, quantity};
 use crate::event_handling::{Event, EventPayload};
+use crate::log_decoding::decode_log;
+
+/// What to do with items that cannot be parsed or decoded.
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseErrorPolicy {
+    /// Skip the item silently
+    #[default]
+    Ignore,
+    /// Skip the item and print a warning
+    Warn,
+    /// Stop indexing
+    Panic,
+}
+
+impl ParseErrorPolicy {
+    pub fn handle(&self, item: &str, error: impl fmt::Display) {
+        match self {
+            ParseErrorPolicy::Ignore => {}
+            ParseErrorPolicy::Warn => {
+                eprintln!("Warning: skipping {} due to parse error: {}", item, error)
+            }
+            ParseErrorPolicy::Panic => panic!("cannot parse {}: {}", item, error),
+        }
+    }
+}
 
 pub fn event_stream_from_vector(
     events: Vec<Event>,
@@ -28,26 +60,22 @@ pub fn event_stream_from_vector(
 pub fn event_stream_from_ndjson_file(
     file: File,
     start: usize,
-    warn_on_unparseable_items: bool,
+    on_parse_error: ParseErrorPolicy,
 ) -> impl Stream<Item = (Event, usize)> {
-    event_stream_from_buf_reader(io::BufReader::new(file), start, warn_on_unparseable_items)
+    event_stream_from_buf_reader(io::BufReader::new(file), start, on_parse_error)
 }
 
 pub fn event_stream_from_ndjson_stdin(
     start: usize,
-    warn_on_unparseable_items: bool,
+    on_parse_error: ParseErrorPolicy,
 ) -> impl Stream<Item = (Event, usize)> {
-    event_stream_from_buf_reader(
-        io::BufReader::new(io::stdin()),
-        start,
-        warn_on_unparseable_items,
-    )
+    event_stream_from_buf_reader(io::BufReader::new(io::stdin()), start, on_parse_error)
 }
 
 pub fn event_stream_from_buf_reader<R: io::BufRead>(
     reader: R,
     start: usize,
-    warn_on_unparseable_items: bool,
+    on_parse_error: ParseErrorPolicy,
 ) -> impl Stream<Item = (Event, usize)> {
     let mut index = 0;
     stream! {
@@ -60,11 +88,79 @@ pub fn event_stream_from_buf_reader<R: io::BufRead>(
                         yield (event, index);
                     }
                     Err(err) => {
-                        // TODO propagate error instead of panicking. Might need to
-                        // use try_stream!
-                        if warn_on_unparseable_items {
-                            eprintln!("Warning: skipping event due to parse error: {}. Data: {}", err, line_content);
-                        }
+                        on_parse_error.handle("event", format!("{}. Data: {}", err, line_content));
+                    }
+                }
+            }
+            index += 1;
+        }
+    }
+}
+
+/// A raw EVM log as exported by a node or an archive. Block numbers and log
+/// indexes may be given as JSON numbers as well as JSON-RPC hex quantities.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RawLog {
+    #[serde(with = "checksummed_address")]
+    address: Address,
+    topics: Vec<H256>,
+    data: Bytes,
+    #[serde(deserialize_with = "quantity::deserialize")]
+    block_number: u64,
+    #[serde(deserialize_with = "quantity::deserialize")]
+    log_index: u64,
+}
+
+impl From<RawLog> for Log {
+    fn from(raw_log: RawLog) -> Self {
+        Log {
+            address: raw_log.address,
+            topics: raw_log.topics,
+            data: raw_log.data,
+            block_number: Some(raw_log.block_number.into()),
+            log_index: Some(raw_log.log_index.into()),
+            ..Default::default()
+        }
+    }
+}
+
+pub fn event_stream_from_raw_log_ndjson_stdin(
+    chain_id: u64,
+    start: usize,
+    on_parse_error: ParseErrorPolicy,
+) -> impl Stream<Item = (Event, usize)> {
+    event_stream_from_raw_log_buf_reader(
+        io::BufReader::new(io::stdin()),
+        chain_id,
+        start,
+        on_parse_error,
+    )
+}
+
+/// Reads raw logs as NDJSON and decodes them into events. Logs of
+/// unsupported events are skipped silently.
+pub fn event_stream_from_raw_log_buf_reader<R: io::BufRead>(
+    reader: R,
+    chain_id: u64,
+    start: usize,
+    on_parse_error: ParseErrorPolicy,
+) -> impl Stream<Item = (Event, usize)> {
+    let mut index = 0;
+    stream! {
+        for line in reader.lines() {
+            if index >= start {
+                let line_content = line.unwrap();
+                let decode_result = serde_json::from_str::<RawLog>(&line_content)
+                    .map_err(|err| err.to_string())
+                    .and_then(|raw_log| decode_log(chain_id, &raw_log.into()).map_err(|err| err.to_string()));
+                match decode_result {
+                    Ok(Some(event)) => {
+                        yield (event, index);
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        on_parse_error.handle("log", format!("{}. Data: {}", err, line_content));
                     }
                 }
             }
@@ -77,6 +173,7 @@ pub fn event_stream_from_buf_reader<R: io::BufRead>(
 mod tests {
     use std::fs::OpenOptions;
 
+    use ethers::types::{Address, U256};
     use tempfile::NamedTempFile;
 
     use super::*;
@@ -90,8 +187,11 @@ mod tests {
         }
         let file_name = tmp_file.path();
 
-        let file_event_source =
-            event_stream_from_ndjson_file(File::open(file_name).unwrap(), 0, false);
+        let file_event_source = event_stream_from_ndjson_file(
+            File::open(file_name).unwrap(),
+            0,
+            ParseErrorPolicy::Ignore,
+        );
         pin_mut!(file_event_source);
 
         let (event, index) = file_event_source.next().await.unwrap();
@@ -99,6 +199,43 @@ mod tests {
         assert_eq!(event.block_number, 10);
     }
 
+    #[tokio::test]
+    async fn test_event_stream_from_raw_log_buf_reader() {
+        let logs = [
+            // ProjectCreated(1, 0xf39F...)
+            r#"{"address":"0x6294bed5B884Ae18bf737793Ef9415069Bf4bc11","topics":["0x63c92f9505d420bff631cb9df33be952bdc11e2118da36a850b43e6bcc4ce4de","0x0000000000000000000000000000000000000000000000000000000000000001","0x000000000000000000000000f39fd6e51aad88f6f4ce6ab8827279cfffb92266"],"data":"0x","blockNumber":"0x10","logIndex":"0x0"}"#,
+            // Transfer(address,address,uint256), not supported
+            r#"{"address":"0x6294bed5B884Ae18bf737793Ef9415069Bf4bc11","topics":["0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"],"data":"0x","blockNumber":17,"logIndex":0}"#,
+            r#"not json"#,
+            // OwnerAdded(1, 0xf39F...)
+            r#"{"address":"0x6294bed5B884Ae18bf737793Ef9415069Bf4bc11","topics":["0xab7a51f59a55e3b65bbabf99457f8955ff12366d20e368988c35d2eab9bd8df9","0x0000000000000000000000000000000000000000000000000000000000000001","0x000000000000000000000000f39fd6e51aad88f6f4ce6ab8827279cfffb92266"],"data":"0x","blockNumber":18,"logIndex":2}"#,
+        ];
+        let input = logs.join("\n");
+        let raw_log_event_source = event_stream_from_raw_log_buf_reader(
+            BufReader::new(input.as_bytes()),
+            58008,
+            0,
+            ParseErrorPolicy::Ignore,
+        );
+        pin_mut!(raw_log_event_source);
+
+        let (event, index) = raw_log_event_source.next().await.unwrap();
+        assert_eq!(index, 0);
+        assert_eq!(event.chain_id, 58008);
+        assert_eq!(event.block_number, 16);
+        assert!(
+            matches!(event.data, EventPayload::ProjectCreated { project_id } if project_id == U256::one())
+        );
+
+        let (event, index) = raw_log_event_source.next().await.unwrap();
+        assert_eq!(index, 3);
+        assert_eq!(event.block_number, 18);
+        assert_eq!(event.log_index, 2);
+        assert!(matches!(event.data, EventPayload::OwnerAdded { .. }));
+
+        assert!(raw_log_event_source.next().await.is_none());
+    }
+
     #[tokio::test]
     async fn test_event_stream_from_vector() {
         let event_data = get_sample_events();
@@ -133,20 +270,20 @@ mod tests {
         vec![
             Event {
                 chain_id: 1,
-                address: "0x123".to_string(),
+                address: Address::from_low_u64_be(0x123),
                 block_number: 10,
                 log_index: 0,
                 data: EventPayload::ProjectCreated {
-                    project_id: "proj-123".to_string(),
+                    project_id: U256::from(123),
                 },
             },
             Event {
                 chain_id: 1,
-                address: "0x123".to_string(),
+                address: Address::from_low_u64_be(0x123),
                 block_number: 20,
                 log_index: 1,
                 data: EventPayload::MetadataUpdated {
-                    project_id: "proj-123".to_string(),
+                    project_id: U256::from(123),
                     meta_ptr: MetaPtr {
                         pointer: "123".to_string(),
                     },
@@ -154,12 +291,12 @@ mod tests {
             },
             Event {
                 chain_id: 1,
-                address: "0x123".to_string(),
+                address: Address::from_low_u64_be(0x123),
                 block_number: 30,
                 log_index: 1,
                 data: EventPayload::OwnerAdded {
-                    project_id: "proj-123".to_string(),
-                    owner: "0x123".to_string(),
+                    project_id: U256::from(123),
+                    owner: Address::from_low_u64_be(0x123),
                 },
             },
         ]
@@ -0,0 +1,295 @@
+//! Writes the tables of a `Store` as files, one per table.
+
+use clap::ValueEnum;
+use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::changes::Value;
+use crate::json_sink::json_value;
+use crate::store::{table_schema, ColumnType, Store, TableSchema};
+
+/// Tables written by `export`; `pending_metadata` only matters to a
+/// database that keeps being indexed.
+pub const EXPORTED_TABLES: &[&str] = &["project", "round", "application"];
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    /// A JSON array of objects
+    Json,
+    /// Comma-separated values with a header line
+    Csv,
+    /// Apache Parquet, uncompressed
+    Parquet,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// Writes `<table>.<format>` into `directory` for each exported table,
+/// creating the directory if needed. Returns the paths written.
+pub fn export(store: &Store, format: ExportFormat, directory: &Path) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(directory)?;
+    let mut paths = vec![];
+    for table in EXPORTED_TABLES {
+        let schema = table_schema(table).expect("exported tables are in the schema");
+        let path = directory.join(format!("{}.{}", table, format.extension()));
+        let file = File::create(&path)?;
+        match format {
+            ExportFormat::Json => write_json(store, schema, BufWriter::new(file))?,
+            ExportFormat::Csv => write_csv(store, schema, BufWriter::new(file))?,
+            ExportFormat::Parquet => write_parquet(store, schema, file)?,
+        }
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// `value` as text, as it would be printed by the database.
+fn text_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Integer(value) => Some(value.to_string()),
+        Value::Numeric(value) => Some(value.to_string()),
+        Value::Text(value) | Value::Json(value) => Some(value.clone()),
+        Value::Search(texts) => Some(texts.join("\n")),
+    }
+}
+
+fn write_json(store: &Store, schema: &TableSchema, mut writer: impl Write) -> io::Result<()> {
+    write!(writer, "[")?;
+    for (position, record) in store.rows(schema.name).enumerate() {
+        let object: serde_json::Map<String, serde_json::Value> = schema
+            .columns
+            .iter()
+            .map(|(column, _)| (column.to_string(), json_value(&record[*column])))
+            .collect();
+        let separator = if position == 0 { "" } else { "," };
+        write!(
+            writer,
+            "{}\n{}",
+            separator,
+            serde_json::Value::Object(object)
+        )?;
+    }
+    writeln!(writer, "\n]")?;
+    writer.flush()
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn write_csv(store: &Store, schema: &TableSchema, mut writer: impl Write) -> io::Result<()> {
+    let header: Vec<&str> = schema.columns.iter().map(|(column, _)| *column).collect();
+    writeln!(writer, "{}", header.join(","))?;
+    for record in store.rows(schema.name) {
+        let fields: Vec<String> = schema
+            .columns
+            .iter()
+            .map(|(column, _)| {
+                text_value(&record[*column])
+                    .map(|value| csv_field(&value))
+                    .unwrap_or_default()
+            })
+            .collect();
+        writeln!(writer, "{}", fields.join(","))?;
+    }
+    writer.flush()
+}
+
+/// Block numbers and chain ids are INT64 columns; everything else, uint256
+/// values included, UTF8 strings, since uint256 does not fit Parquet's
+/// decimals as most readers support them.
+fn write_parquet(store: &Store, schema: &TableSchema, file: File) -> io::Result<()> {
+    let fields: Vec<String> = schema
+        .columns
+        .iter()
+        .map(|(column, column_type)| match column_type {
+            ColumnType::Integer => format!("OPTIONAL INT64 {};", column),
+            _ => format!("OPTIONAL BYTE_ARRAY {} (UTF8);", column),
+        })
+        .collect();
+    let message = format!("message {} {{ {} }}", schema.name, fields.join(" "));
+    let parquet_schema = Arc::new(parse_message_type(&message).map_err(io::Error::other)?);
+    let mut writer = SerializedFileWriter::new(
+        file,
+        parquet_schema,
+        Arc::new(WriterProperties::builder().build()),
+    )
+    .map_err(io::Error::other)?;
+
+    let records: Vec<_> = store.rows(schema.name).collect();
+    let mut row_group = writer.next_row_group().map_err(io::Error::other)?;
+    for (column, column_type) in schema.columns {
+        let values: Vec<&Value> = records.iter().map(|record| &record[*column]).collect();
+        // Definition level 0 is NULL, 1 a value
+        let levels: Vec<i16> = values
+            .iter()
+            .map(|value| i16::from(**value != Value::Null))
+            .collect();
+        let mut column_writer = row_group
+            .next_column()
+            .map_err(io::Error::other)?
+            .expect("one column writer per schema column");
+        match column_type {
+            ColumnType::Integer => {
+                let integers: Vec<i64> = values
+                    .iter()
+                    .filter_map(|value| match value {
+                        Value::Integer(value) => Some(*value as i64),
+                        _ => None,
+                    })
+                    .collect();
+                column_writer
+                    .typed::<Int64Type>()
+                    .write_batch(&integers, Some(&levels), None)
+            }
+            _ => {
+                let texts: Vec<ByteArray> = values
+                    .iter()
+                    .filter_map(|value| text_value(value))
+                    .map(|text| ByteArray::from(text.into_bytes()))
+                    .collect();
+                column_writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&texts, Some(&levels), None)
+            }
+        }
+        .map_err(io::Error::other)?;
+        column_writer.close().map_err(io::Error::other)?;
+    }
+    row_group.close().map_err(io::Error::other)?;
+    writer.close().map_err(io::Error::other)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handling::{event_to_changeset_with_metadata, EventPayload, MetaPtr};
+    use crate::test_helpers::event;
+    use ethers::types::U256;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::{Field, Row, RowAccessor};
+
+    fn sample_store() -> Store {
+        let mut store = Store::new();
+        let project_id = U256::from(2).pow(U256::from(128));
+        let events = [
+            (event(1, EventPayload::ProjectCreated { project_id }), None),
+            (
+                event(
+                    2,
+                    EventPayload::MetadataUpdated {
+                        project_id,
+                        meta_ptr: MetaPtr {
+                            pointer: "cid".to_string(),
+                        },
+                    },
+                ),
+                Some(Ok(
+                    r#"{"title":"Foo, \"the\" project","description":"Bar"}"#.to_string(),
+                )),
+            ),
+            (
+                event(
+                    3,
+                    EventPayload::ProjectCreated {
+                        project_id: U256::one(),
+                    },
+                ),
+                None,
+            ),
+        ];
+        for (event, metadata) in events {
+            store
+                .apply_all(&event_to_changeset_with_metadata(&event, metadata).changes)
+                .unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let directory = tempfile::TempDir::new().unwrap();
+        let paths = export(&sample_store(), ExportFormat::Csv, directory.path()).unwrap();
+        assert_eq!(paths.len(), 3);
+
+        let projects = fs::read_to_string(directory.path().join("project.csv")).unwrap();
+        let lines: Vec<&str> = projects.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "chain_id,project_id,created_at_block,metadata,metadata_status,metadata_error,title,description,website,logo_img,banner_img,project_twitter,user_github,project_github,credentials,owners",
+                "1,1,3,,,,,,,,,,,,,[]",
+                r#"1,340282366920938463463374607431768211456,1,"{""title"":""Foo, \""the\"" project"",""description"":""Bar""}",ok,,"Foo, ""the"" project",Bar,,,,,,,{},[]"#,
+            ]
+        );
+        let rounds = fs::read_to_string(directory.path().join("round.csv")).unwrap();
+        assert_eq!(rounds, "chain_id,round_address,created_at_block\n");
+    }
+
+    #[test]
+    fn test_export_json() {
+        let directory = tempfile::TempDir::new().unwrap();
+        export(&sample_store(), ExportFormat::Json, directory.path()).unwrap();
+
+        let projects: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(directory.path().join("project.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(projects.as_array().unwrap().len(), 2);
+        assert_eq!(projects[0]["project_id"], "1");
+        assert_eq!(projects[0]["metadata"], serde_json::Value::Null);
+        assert_eq!(projects[1]["title"], "Foo, \"the\" project");
+        assert_eq!(projects[1]["owners"], serde_json::json!([]));
+        let rounds: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(directory.path().join("round.json")).unwrap())
+                .unwrap();
+        assert_eq!(rounds, serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_export_parquet() {
+        let directory = tempfile::TempDir::new().unwrap();
+        export(&sample_store(), ExportFormat::Parquet, directory.path()).unwrap();
+
+        let reader = SerializedFileReader::new(
+            File::open(directory.path().join("project.parquet")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        let rows: Vec<Row> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        assert_eq!(rows[0].get_long(0).unwrap(), 1);
+        assert_eq!(rows[0].get_string(1).unwrap(), "1");
+        assert_eq!(rows[0].get_long(2).unwrap(), 3);
+        let (column, metadata) = rows[0].get_column_iter().nth(3).unwrap();
+        assert_eq!((column.as_str(), metadata), ("metadata", &Field::Null));
+        assert_eq!(
+            rows[1].get_string(1).unwrap(),
+            "340282366920938463463374607431768211456"
+        );
+        assert_eq!(rows[1].get_string(6).unwrap(), "Foo, \"the\" project");
+    }
+}
@@ -0,0 +1,556 @@
+//! Read-only GraphQL API over the tables written by the indexer.
+
+use async_graphql::{
+    ComplexObject, Context, EmptyMutation, EmptySubscription, Json, Object, Result, Schema,
+    SchemaBuilder, SimpleObject,
+};
+use std::sync::Arc;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Client, Row};
+
+pub type ApiSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+/// Most items returned by a list field, whatever `first` asks for.
+pub const MAX_PAGE_SIZE: i64 = 1000;
+
+/// Deepest selection accepted. Rounds and applications refer to each other,
+/// so queries could otherwise nest them without end.
+pub const MAX_DEPTH: usize = 6;
+
+/// Most values a query may ask for, counting each field of each item of a
+/// list as many times as `first` allows.
+pub const MAX_COMPLEXITY: usize = 200_000;
+
+/// Resolves queries with `client`, which must be connected to a database
+/// the indexer writes to.
+pub fn schema(client: Arc<Client>) -> ApiSchema {
+    schema_builder().data(client).finish()
+}
+
+fn schema_builder() -> SchemaBuilder<Query, EmptyMutation, EmptySubscription> {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+}
+
+#[derive(SimpleObject)]
+pub struct Project {
+    pub chain_id: i64,
+    /// uint256, in decimal
+    pub project_id: String,
+    pub created_at_block: i64,
+    pub metadata: Option<Json<serde_json::Value>>,
+    /// ok, pending or invalid
+    pub metadata_status: Option<String>,
+    pub metadata_error: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub website: Option<String>,
+    pub logo_img: Option<String>,
+    pub banner_img: Option<String>,
+    pub project_twitter: Option<String>,
+    pub user_github: Option<String>,
+    pub project_github: Option<String>,
+    pub credentials: Option<Json<serde_json::Value>>,
+    pub owners: Vec<String>,
+}
+
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct Round {
+    pub chain_id: i64,
+    pub round_address: String,
+    pub created_at_block: i64,
+}
+
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct Application {
+    pub chain_id: i64,
+    pub round_address: String,
+    /// uint256, in decimal
+    pub index: String,
+    /// bytes32 identifying the project across chains
+    pub project_id: String,
+    pub status: String,
+    pub created_at_block: i64,
+    pub status_updated_at_block: i64,
+    /// Always 0, since Voted events are not indexed yet
+    pub vote_count: i32,
+    /// Always 0, since Voted events are not indexed yet
+    pub unique_contributor_count: i32,
+    pub metadata: Option<Json<serde_json::Value>>,
+    pub status_snapshots: Option<Json<serde_json::Value>>,
+}
+
+const PROJECT_COLUMNS: &str = "chain_id, project_id::TEXT, created_at_block, metadata::TEXT, metadata_status, metadata_error, title, description, website, logo_img, banner_img, project_twitter, user_github, project_github, credentials::TEXT, owners::TEXT";
+const ROUND_COLUMNS: &str = "chain_id, round_address, created_at_block";
+const APPLICATION_COLUMNS: &str = "chain_id, round_address, \"index\"::TEXT, project_id, status, created_at_block, status_updated_at_block, vote_count, unique_contributor_count, metadata::TEXT, status_snapshots::TEXT";
+
+fn json(text: Option<String>) -> Option<Json<serde_json::Value>> {
+    text.and_then(|text| serde_json::from_str(&text).ok())
+        .map(Json)
+}
+
+impl From<&Row> for Project {
+    fn from(row: &Row) -> Self {
+        Project {
+            chain_id: row.get(0),
+            project_id: row.get(1),
+            created_at_block: row.get(2),
+            metadata: json(row.get(3)),
+            metadata_status: row.get(4),
+            metadata_error: row.get(5),
+            title: row.get(6),
+            description: row.get(7),
+            website: row.get(8),
+            logo_img: row.get(9),
+            banner_img: row.get(10),
+            project_twitter: row.get(11),
+            user_github: row.get(12),
+            project_github: row.get(13),
+            credentials: json(row.get(14)),
+            owners: serde_json::from_str(row.get(15)).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<&Row> for Round {
+    fn from(row: &Row) -> Self {
+        Round {
+            chain_id: row.get(0),
+            round_address: row.get(1),
+            created_at_block: row.get(2),
+        }
+    }
+}
+
+impl From<&Row> for Application {
+    fn from(row: &Row) -> Self {
+        Application {
+            chain_id: row.get(0),
+            round_address: row.get(1),
+            index: row.get(2),
+            project_id: row.get(3),
+            status: row.get(4),
+            created_at_block: row.get(5),
+            status_updated_at_block: row.get(6),
+            vote_count: row.get(7),
+            unique_contributor_count: row.get(8),
+            metadata: json(row.get(9)),
+            status_snapshots: json(row.get(10)),
+        }
+    }
+}
+
+async fn query<T: for<'a> From<&'a Row>>(
+    ctx: &Context<'_>,
+    sql: &str,
+    params: &[&(dyn ToSql + Sync)],
+) -> Result<Vec<T>> {
+    let client = ctx.data::<Arc<Client>>()?;
+    let rows = client.query(sql, params).await?;
+    Ok(rows.iter().map(T::from).collect())
+}
+
+/// Addresses are stored in lowercase.
+fn address(address: Option<String>) -> Option<String> {
+    address.map(|address| address.to_lowercase())
+}
+
+fn page_size(first: i64) -> i64 {
+    first.clamp(0, MAX_PAGE_SIZE)
+}
+
+/// Complexity of a list field, as many times that of an item as it returns
+/// items at most.
+fn list_complexity(first: i64, child_complexity: usize) -> usize {
+    page_size(first) as usize * child_complexity
+}
+
+/// Postgres rejects a negative OFFSET.
+fn offset(skip: i64) -> i64 {
+    skip.max(0)
+}
+
+async fn applications(
+    ctx: &Context<'_>,
+    chain_id: Option<i64>,
+    round_address: Option<String>,
+    status: Option<String>,
+    project_id: Option<String>,
+    first: i64,
+    skip: i64,
+) -> Result<Vec<Application>> {
+    query(
+        ctx,
+        &format!(
+            "SELECT {} FROM application WHERE ($1::BIGINT IS NULL OR chain_id = $1) AND ($2::TEXT IS NULL OR round_address = $2) AND ($3::TEXT IS NULL OR status = $3) AND ($4::TEXT IS NULL OR project_id = $4) ORDER BY application.chain_id, application.round_address, application.\"index\" LIMIT $5 OFFSET $6",
+            APPLICATION_COLUMNS
+        ),
+        &[
+            &chain_id,
+            &address(round_address),
+            &status,
+            &address(project_id),
+            &page_size(first),
+            &offset(skip),
+        ],
+    )
+    .await
+}
+
+#[ComplexObject]
+impl Round {
+    /// Applications to this round, optionally only those with `status`
+    /// (e.g. PENDING)
+    #[graphql(complexity = "list_complexity(first, child_complexity)")]
+    async fn applications(
+        &self,
+        ctx: &Context<'_>,
+        status: Option<String>,
+        #[graphql(default = 100)] first: i64,
+        #[graphql(default = 0)] skip: i64,
+    ) -> Result<Vec<Application>> {
+        applications(
+            ctx,
+            Some(self.chain_id),
+            Some(self.round_address.clone()),
+            status,
+            None,
+            first,
+            skip,
+        )
+        .await
+    }
+}
+
+#[ComplexObject]
+impl Application {
+    async fn round(&self, ctx: &Context<'_>) -> Result<Option<Round>> {
+        Query
+            .round(ctx, self.chain_id, self.round_address.clone())
+            .await
+    }
+}
+
+pub struct Query;
+
+/// List fields return at most `first` items (100 by default, 1000 at most)
+/// after skipping `skip` (none if negative), in key order.
+#[Object]
+impl Query {
+    #[graphql(complexity = "list_complexity(first, child_complexity)")]
+    async fn projects(
+        &self,
+        ctx: &Context<'_>,
+        chain_id: Option<i64>,
+        #[graphql(desc = "Only projects this address owns")] owner: Option<String>,
+        #[graphql(default = 100)] first: i64,
+        #[graphql(default = 0)] skip: i64,
+    ) -> Result<Vec<Project>> {
+        query(
+            ctx,
+            &format!(
+                "SELECT {} FROM project WHERE ($1::BIGINT IS NULL OR chain_id = $1) AND ($2::TEXT IS NULL OR owners ? $2) ORDER BY project.chain_id, project.project_id LIMIT $3 OFFSET $4",
+                PROJECT_COLUMNS
+            ),
+            &[&chain_id, &address(owner), &page_size(first), &offset(skip)],
+        )
+        .await
+    }
+
+    async fn project(
+        &self,
+        ctx: &Context<'_>,
+        chain_id: i64,
+        #[graphql(desc = "uint256, in decimal")] project_id: String,
+    ) -> Result<Option<Project>> {
+        let projects = query(
+            ctx,
+            &format!(
+                "SELECT {} FROM project WHERE chain_id = $1 AND project_id = $2::TEXT::NUMERIC",
+                PROJECT_COLUMNS
+            ),
+            &[&chain_id, &project_id],
+        )
+        .await?;
+        Ok(projects.into_iter().next())
+    }
+
+    #[graphql(complexity = "list_complexity(first, child_complexity)")]
+    async fn rounds(
+        &self,
+        ctx: &Context<'_>,
+        chain_id: Option<i64>,
+        #[graphql(default = 100)] first: i64,
+        #[graphql(default = 0)] skip: i64,
+    ) -> Result<Vec<Round>> {
+        query(
+            ctx,
+            &format!(
+                "SELECT {} FROM round WHERE ($1::BIGINT IS NULL OR chain_id = $1) ORDER BY chain_id, round_address LIMIT $2 OFFSET $3",
+                ROUND_COLUMNS
+            ),
+            &[&chain_id, &page_size(first), &offset(skip)],
+        )
+        .await
+    }
+
+    async fn round(
+        &self,
+        ctx: &Context<'_>,
+        chain_id: i64,
+        round_address: String,
+    ) -> Result<Option<Round>> {
+        let rounds = query(
+            ctx,
+            &format!(
+                "SELECT {} FROM round WHERE chain_id = $1 AND round_address = $2 LIMIT 1",
+                ROUND_COLUMNS
+            ),
+            &[&chain_id, &address(Some(round_address))],
+        )
+        .await?;
+        Ok(rounds.into_iter().next())
+    }
+
+    #[graphql(
+        name = "applications",
+        complexity = "list_complexity(first, child_complexity)"
+    )]
+    #[allow(clippy::too_many_arguments)]
+    async fn list_applications(
+        &self,
+        ctx: &Context<'_>,
+        chain_id: Option<i64>,
+        round_address: Option<String>,
+        #[graphql(desc = "e.g. PENDING")] status: Option<String>,
+        #[graphql(desc = "bytes32, in hex")] project_id: Option<String>,
+        #[graphql(default = 100)] first: i64,
+        #[graphql(default = 0)] skip: i64,
+    ) -> Result<Vec<Application>> {
+        applications(
+            ctx,
+            chain_id,
+            round_address,
+            status,
+            project_id,
+            first,
+            skip,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::event_handling::{changes_to_sql, MetaPtr};
+    use crate::event_handling::{event_to_changeset_with_metadata, Event, EventPayload};
+    use crate::test_helpers::{client_with_schema, event};
+    use ethers::types::{Address, H256, U256};
+    use serde_json::json;
+
+    /// A schema over sample data, in a transaction that is never committed.
+    async fn sample_schema() -> ApiSchema {
+        let client = client_with_schema().await;
+
+        let round_created = |chain_id, round| Event {
+            chain_id,
+            ..event(
+                1,
+                EventPayload::RoundCreated {
+                    round_address: Address::from_low_u64_be(round),
+                },
+            )
+        };
+        let application = |round, project: u64, index: u64| Event {
+            address: Address::from_low_u64_be(round),
+            ..event(
+                2,
+                EventPayload::NewProjectApplication {
+                    project_id: H256::from_low_u64_be(project),
+                    application_index: U256::from(index),
+                },
+            )
+        };
+        let events = [
+            (
+                event(
+                    1,
+                    EventPayload::ProjectCreated {
+                        project_id: U256::from(10),
+                    },
+                ),
+                None,
+            ),
+            (
+                event(
+                    1,
+                    EventPayload::ProjectCreated {
+                        project_id: U256::from(2),
+                    },
+                ),
+                None,
+            ),
+            (
+                Event {
+                    chain_id: 10,
+                    ..event(
+                        1,
+                        EventPayload::ProjectCreated {
+                            project_id: U256::from(1),
+                        },
+                    )
+                },
+                None,
+            ),
+            (
+                event(
+                    2,
+                    EventPayload::MetadataUpdated {
+                        project_id: U256::from(2),
+                        meta_ptr: MetaPtr {
+                            pointer: "cid".to_string(),
+                        },
+                    },
+                ),
+                Some(Ok(r#"{"title":"Foo","description":"Bar"}"#.to_string())),
+            ),
+            (
+                event(
+                    3,
+                    EventPayload::OwnerAdded {
+                        project_id: U256::from(2),
+                        owner: Address::from_low_u64_be(0xab),
+                    },
+                ),
+                None,
+            ),
+            (round_created(1, 0xa), None),
+            (round_created(10, 0xb), None),
+            (application(0xa, 1, 10), None),
+            (application(0xa, 2, 2), None),
+        ];
+        for (event, metadata) in events {
+            let changes = event_to_changeset_with_metadata(&event, metadata).changes;
+            client
+                .batch_execute(&changes_to_sql(&changes, Dialect::Postgres))
+                .await
+                .unwrap();
+        }
+        client
+            .batch_execute("UPDATE application SET status = 'APPROVED' WHERE \"index\" = 2")
+            .await
+            .unwrap();
+
+        schema(Arc::new(client))
+    }
+
+    #[tokio::test]
+    async fn test_graphql_limits() {
+        let schema = schema_builder().finish();
+        let errors = |query: &'static str| {
+            let schema = &schema;
+            async move { schema.execute(query).await.errors }
+        };
+
+        // Checked before anything is read, so no database is needed
+        let nested = "{ rounds(first: 1) { applications(first: 1) { round { applications(first: 1) { round { applications(first: 1) { index } } } } } } }";
+        assert_eq!(errors(nested).await[0].message, "Query is nested too deep.");
+        let wide =
+            "{ applications(first: 1000) { round { applications(first: 1000) { index } } } }";
+        assert_eq!(errors(wide).await[0].message, "Query is too complex.");
+        // Within the limits, resolving fails for lack of a database instead
+        let full_page = "{ rounds { roundAddress applications { index status metadata } } }";
+        assert!(errors(full_page).await[0].message.contains("Data"));
+    }
+
+    async fn execute(schema: &ApiSchema, query: &str) -> serde_json::Value {
+        let response = schema.execute(query).await;
+        assert_eq!(response.errors, vec![]);
+        response.data.into_json().unwrap()
+    }
+
+    #[ignore = "needs Postgres, see GS_INDEX_TEST_DATABASE"]
+    #[tokio::test]
+    async fn test_graphql_queries() {
+        let schema = sample_schema().await;
+
+        assert_eq!(
+            execute(
+                &schema,
+                "{ projects(chainId: 1) { projectId title owners } }"
+            )
+            .await,
+            json!({ "projects": [
+                { "projectId": "2", "title": "Foo", "owners": ["0x00000000000000000000000000000000000000ab"] },
+                { "projectId": "10", "title": null, "owners": [] },
+            ] })
+        );
+        assert_eq!(
+            execute(
+                &schema,
+                r#"{ projects(owner: "0x00000000000000000000000000000000000000AB") { chainId projectId metadata } }"#
+            )
+            .await,
+            json!({ "projects": [
+                { "chainId": 1, "projectId": "2", "metadata": { "title": "Foo", "description": "Bar" } },
+            ] })
+        );
+        assert_eq!(
+            execute(
+                &schema,
+                "{ projects(first: 1, skip: 2) { chainId projectId } }"
+            )
+            .await,
+            json!({ "projects": [{ "chainId": 10, "projectId": "1" }] })
+        );
+        assert_eq!(
+            execute(
+                &schema,
+                "{ projects(first: 1, skip: -1) { chainId projectId } }"
+            )
+            .await,
+            json!({ "projects": [{ "chainId": 1, "projectId": "2" }] })
+        );
+        assert_eq!(
+            execute(
+                &schema,
+                r#"{ project(chainId: 10, projectId: "1") { createdAtBlock } }"#
+            )
+            .await,
+            json!({ "project": { "createdAtBlock": 1 } })
+        );
+
+        assert_eq!(
+            execute(
+                &schema,
+                r#"{ rounds(chainId: 1) { roundAddress applications(status: "APPROVED") { index status } } }"#
+            )
+            .await,
+            json!({ "rounds": [{
+                "roundAddress": "0x000000000000000000000000000000000000000a",
+                "applications": [{ "index": "2", "status": "APPROVED" }],
+            }] })
+        );
+        assert_eq!(
+            execute(
+                &schema,
+                r#"{ applications(roundAddress: "0x000000000000000000000000000000000000000A", status: "PENDING") { projectId round { chainId } } }"#
+            )
+            .await,
+            json!({ "applications": [{
+                "projectId": format!("{:#x}", H256::from_low_u64_be(1)),
+                "round": { "chainId": 1 },
+            }] })
+        );
+        // uint256 columns are ordered as numbers
+        assert_eq!(
+            execute(&schema, "{ applications(chainId: 1) { index } }").await,
+            json!({ "applications": [{ "index": "2" }, { "index": "10" }] })
+        );
+    }
+}
@@ -1,90 +1,500 @@
 use std::pin::Pin;
 
+use crate::chains::{ChainConfig, ChainSource};
+use crate::checkpoint::CheckpointStore;
+use crate::contract_watchlist::ContractWatchlist;
 use crate::event_handling::{
-    event_to_changeset, ChangeSet, Event, EventPayload, MetaPtr, DB_SCHEMA,
+    event_to_changeset, metadata_pointer, ChangeSet, Event, EventPayload, MetaPtr, MetadataError,
+    DB_SCHEMA,
 };
+use crate::event_source::{
+    event_stream_from_ndjson_file, event_stream_from_raw_log_buf_reader, ParseErrorPolicy,
+};
+use crate::rpc_event_source::{event_stream_from_rpc, event_stream_from_ws, RpcSourceOptions};
 use async_stream::stream;
+use ethers::providers::{Http, Provider};
 use futures::pin_mut;
 use futures::stream::Stream;
 use futures::stream::StreamExt;
+use futures::Future;
 use sea_query::{Expr, Iden, PostgresQueryBuilder, Query};
+use std::fs::File;
+use std::io::{self, BufReader};
 use tokio_postgres::{Client, Connection, Error, NoTls, Transaction};
 
+/// Builds the event stream of one chain, resuming after the chain's
+/// checkpoint and including the contracts discovered in previous runs. Fails
+/// when the chain's file cannot be opened or its RPC URL is not valid.
+async fn chain_event_stream(
+    chain: &ChainConfig,
+    checkpoints: &CheckpointStore,
+    watchlist: ContractWatchlist,
+    on_parse_error: ParseErrorPolicy,
+) -> io::Result<Pin<Box<dyn Stream<Item = (Event, usize)>>>> {
+    let checkpoint = checkpoints.checkpoint(chain.id);
+    let options = RpcSourceOptions {
+        chain_id: chain.id,
+        addresses: chain
+            .addresses()
+            .into_iter()
+            .chain(
+                checkpoints
+                    .contracts()
+                    .iter()
+                    .filter(|contract| contract.chain_id == chain.id)
+                    .map(|contract| contract.address),
+            )
+            .collect(),
+        from_block: chain
+            .start_block
+            .max(checkpoint.map_or(0, |checkpoint| checkpoint.block_number)),
+        to_block: chain.end_block,
+        page_size: chain.page_size,
+        on_parse_error,
+    };
+
+    let events: Pin<Box<dyn Stream<Item = (Event, usize)>>> = match &chain.source {
+        ChainSource::Rpc { url } => {
+            let provider = Provider::<Http>::try_from(url.as_str()).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid RPC URL for {}: {}", chain.name, err),
+                )
+            })?;
+            Box::pin(event_stream_from_rpc(provider, options, watchlist))
+        }
+        ChainSource::Ws { url } => Box::pin(event_stream_from_ws(url.clone(), options, watchlist)),
+        ChainSource::Events { path } => {
+            let file = File::open(path).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("cannot open {} for {}: {}", path, chain.name, err),
+                )
+            })?;
+            Box::pin(event_stream_from_ndjson_file(file, 0, on_parse_error))
+        }
+        ChainSource::RawLogs { path } => {
+            let file = File::open(path).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("cannot open {} for {}: {}", path, chain.name, err),
+                )
+            })?;
+            Box::pin(event_stream_from_raw_log_buf_reader(
+                BufReader::new(file),
+                chain.id,
+                0,
+                on_parse_error,
+            ))
+        }
+    };
+
+    let chain_id = chain.id;
+    Ok(Box::pin(events.filter(move |(event, _index)| {
+        futures::future::ready(
+            event.chain_id == chain_id
+                && checkpoint.is_none_or(|checkpoint| checkpoint.is_before(event)),
+        )
+    })))
+}
+
+/// Merges the event streams of several chains. Events of each chain keep
+/// their order; events of different chains are interleaved as they become
+/// available. Items are numbered in the order they come out of the merged
+/// stream.
+pub async fn event_stream_from_chains(
+    chains: &[ChainConfig],
+    checkpoints: &CheckpointStore,
+    watchlist: ContractWatchlist,
+    on_parse_error: ParseErrorPolicy,
+) -> io::Result<impl Stream<Item = (Event, usize)>> {
+    let mut chain_streams = vec![];
+    for chain in chains {
+        chain_streams
+            .push(chain_event_stream(chain, checkpoints, watchlist.clone(), on_parse_error).await?);
+    }
+
+    Ok(futures::stream::select_all(chain_streams)
+        .enumerate()
+        .map(|(index, (event, _chain_index))| (event, index)))
+}
+
+/// Looks up to `concurrency` events ahead and fetches the metadata they refer
+/// to concurrently. Items still come out in stream order, each with its
+/// metadata, if any.
+pub fn prefetch_metadata<F, Fut>(
+    events: impl Stream<Item = (Event, usize)>,
+    fetch: F,
+    concurrency: usize,
+) -> impl Stream<Item = (Event, usize, Option<Result<String, MetadataError>>)>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<String, MetadataError>>,
+{
+    events
+        .map(move |(event, index)| {
+            let fetching = metadata_pointer(&event).map(|pointer| fetch(pointer.to_string()));
+            async move {
+                let metadata = match fetching {
+                    Some(fetching) => Some(fetching.await),
+                    None => None,
+                };
+                (event, index, metadata)
+            }
+        })
+        .buffered(concurrency)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::changes::Value;
+    use crate::dialect::Dialect;
     use crate::event_source::event_stream_from_vector;
+    use crate::sink::Sink;
+    use crate::store::Store;
+    use crate::test_helpers::{connect, event, transaction_with_schema};
+    use ethers::types::{Address, H256, U256};
+    use serde_json::to_string;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tempfile::{NamedTempFile, TempDir};
+
+    fn write_events_file(events: &[Event]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        for event in events {
+            writeln!(file, "{}", to_string(event).unwrap()).unwrap();
+        }
+        file
+    }
+
+    fn project_created(chain_id: u64, block_number: u64, project_id: u64) -> Event {
+        Event {
+            chain_id,
+            ..event(
+                block_number,
+                EventPayload::ProjectCreated {
+                    project_id: U256::from(project_id),
+                },
+            )
+        }
+    }
 
+    fn events_chain(id: u64, file: &NamedTempFile) -> ChainConfig {
+        ChainConfig {
+            id,
+            name: format!("chain-{}", id),
+            source: ChainSource::Events {
+                path: file.path().to_str().unwrap().to_string(),
+            },
+            registry: None,
+            round_factory: None,
+            rounds: vec![],
+            start_block: 0,
+            end_block: None,
+            page_size: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_event_stream_from_chains() {
+        let mainnet_file = write_events_file(&[
+            project_created(1, 10, 1),
+            project_created(10, 15, 99),
+            project_created(1, 20, 2),
+            project_created(1, 30, 3),
+        ]);
+        let optimism_file =
+            write_events_file(&[project_created(10, 5, 1), project_created(10, 25, 2)]);
+        let chains = vec![
+            events_chain(1, &mainnet_file),
+            events_chain(10, &optimism_file),
+        ];
+
+        let checkpoints_directory = TempDir::new().unwrap();
+        let mut checkpoints =
+            CheckpointStore::load(&checkpoints_directory.path().join("checkpoints.json")).unwrap();
+        checkpoints.update(&project_created(1, 10, 1));
+
+        let event_stream = event_stream_from_chains(
+            &chains,
+            &checkpoints,
+            ContractWatchlist::new(),
+            ParseErrorPolicy::Ignore,
+        )
+        .await
+        .unwrap();
+        let mut events: Vec<(u64, u64)> = event_stream
+            .map(|(event, _index)| (event.chain_id, event.block_number))
+            .collect()
+            .await;
+        events.sort();
+
+        assert_eq!(events, vec![(1, 20), (1, 30), (10, 5), (10, 25)]);
+    }
+
+    #[ignore = "needs Postgres, see GS_INDEX_TEST_DATABASE"]
     #[tokio::test]
     async fn test_project_created() {
         let events = vec![Event {
-            chain_id: 1,
-            address: "0x123".to_string(),
-            block_number: 4242,
             log_index: 1,
-            data: EventPayload::ProjectCreated {
-                project_id: "proj-123".to_string(),
-            },
+            ..event(
+                4242,
+                EventPayload::ProjectCreated {
+                    project_id: U256::from(123),
+                },
+            )
         }];
 
-        let db_dump = event_stream_to_db_dump(events).await.unwrap();
+        let db_dump = event_stream_to_db_dump(events).await;
 
         insta::assert_yaml_snapshot!(db_dump);
     }
 
+    #[ignore = "needs Postgres, see GS_INDEX_TEST_DATABASE"]
     #[tokio::test]
     async fn test_project_created_and_metadata_updated() {
         let events = vec![
             Event {
-                chain_id: 1,
-                address: "0x123".to_string(),
-                block_number: 4242,
                 log_index: 1,
-                data: EventPayload::ProjectCreated {
-                    project_id: "proj-123".to_string(),
-                },
+                ..event(
+                    4242,
+                    EventPayload::ProjectCreated {
+                        project_id: U256::from(123),
+                    },
+                )
             },
             Event {
-                chain_id: 1,
-                address: "0x123".to_string(),
-                block_number: 4242,
                 log_index: 2,
-                data: EventPayload::MetadataUpdated {
-                    project_id: "proj-123".to_string(),
-                    meta_ptr: MetaPtr {
-                        pointer: "123".to_string(),
+                ..event(
+                    4242,
+                    EventPayload::MetadataUpdated {
+                        project_id: U256::from(123),
+                        meta_ptr: MetaPtr {
+                            pointer: "123".to_string(),
+                        },
                     },
-                },
+                )
             },
         ];
 
-        let db_dump = event_stream_to_db_dump(events).await.unwrap();
+        let db_dump = event_stream_to_db_dump(events).await;
 
         insta::assert_yaml_snapshot!(db_dump);
     }
 
-    fn dummy_ipfs_getter(_url: String) -> Pin<Box<dyn futures::Future<Output = String> + Send>> {
-        Box::pin(async move { r#"{ "foo": "bar" }"#.to_string() })
+    #[ignore = "needs Postgres, see GS_INDEX_TEST_DATABASE"]
+    #[tokio::test]
+    async fn test_project_owners() {
+        let owner = |data| Event {
+            log_index: 1,
+            ..event(4242, data)
+        };
+        let events = vec![
+            project_created(1, 4242, 123),
+            owner(EventPayload::OwnerAdded {
+                project_id: U256::from(123),
+                owner: Address::from_low_u64_be(0xa),
+            }),
+            owner(EventPayload::OwnerAdded {
+                project_id: U256::from(123),
+                owner: Address::from_low_u64_be(0xb),
+            }),
+            owner(EventPayload::OwnerRemoved {
+                project_id: U256::from(123),
+                owner: Address::from_low_u64_be(0xa),
+            }),
+        ];
+
+        let db_dump = event_stream_to_db_dump(events).await;
+
+        insta::assert_yaml_snapshot!(db_dump);
+    }
+
+    #[tokio::test]
+    async fn test_handlers_in_memory() {
+        let project_id = U256::from(123);
+        let events = vec![
+            project_created(1, 4242, 123),
+            metadata_updated(4243, "123"),
+            event(
+                4244,
+                EventPayload::OwnerAdded {
+                    project_id,
+                    owner: Address::from_low_u64_be(0xa),
+                },
+            ),
+            event(
+                4245,
+                EventPayload::OwnerAdded {
+                    project_id,
+                    owner: Address::from_low_u64_be(0xb),
+                },
+            ),
+            event(
+                4246,
+                EventPayload::OwnerRemoved {
+                    project_id,
+                    owner: Address::from_low_u64_be(0xa),
+                },
+            ),
+            event(
+                4247,
+                EventPayload::RoundCreated {
+                    round_address: Address::from_low_u64_be(0x123),
+                },
+            ),
+            event(
+                4248,
+                EventPayload::NewProjectApplication {
+                    project_id: H256::from_low_u64_be(123),
+                    application_index: U256::zero(),
+                },
+            ),
+        ];
+
+        let store = event_stream_to_store(events).await;
+
+        let project = store
+            .get("project", &[Value::Integer(1), Value::Numeric(project_id)])
+            .unwrap();
+        assert_eq!(project["created_at_block"], Value::Integer(4242));
+        assert_eq!(project["title"], Value::Text("Foo".to_string()));
+        assert_eq!(project["description"], Value::Text("Bar".to_string()));
+        assert_eq!(project["metadata_status"], Value::Text("ok".to_string()));
+        assert_eq!(
+            project["owners"],
+            Value::Json(r#"["0x000000000000000000000000000000000000000b"]"#.to_string())
+        );
+        assert_eq!(store.rows("pending_metadata").count(), 0);
+
+        let round = store.rows("round").next().unwrap();
+        assert_eq!(round["created_at_block"], Value::Integer(4247));
+        let application = store
+            .get(
+                "application",
+                &[
+                    Value::Integer(1),
+                    Value::Text("0x0000000000000000000000000000000000000123".to_string()),
+                    Value::Numeric(U256::zero()),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            application["project_id"],
+            Value::Text(format!("{:#x}", H256::from_low_u64_be(123)))
+        );
+        assert_eq!(application["status"], Value::Text("PENDING".to_string()));
+    }
+
+    fn metadata_updated(block_number: u64, pointer: &str) -> Event {
+        event(
+            block_number,
+            EventPayload::MetadataUpdated {
+                project_id: U256::from(123),
+                meta_ptr: MetaPtr {
+                    pointer: pointer.to_string(),
+                },
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_metadata_keeps_stream_order() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let mut events = vec![];
+        for block_number in 0..8 {
+            events.push(metadata_updated(
+                block_number,
+                &format!("doc{}", block_number),
+            ));
+            events.push(Event {
+                log_index: 1,
+                ..event(
+                    block_number,
+                    EventPayload::ProjectCreated {
+                        project_id: U256::from(block_number),
+                    },
+                )
+            });
+        }
+
+        // Earlier documents take longer, so they complete in reverse order.
+        let fetch = |pointer: String| {
+            let in_flight = in_flight.clone();
+            let max_in_flight = max_in_flight.clone();
+            async move {
+                let count = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(count, Ordering::SeqCst);
+                let position: u64 = pointer["doc".len()..].parse().unwrap();
+                tokio::time::sleep(Duration::from_millis(10 * (8 - position))).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok(pointer)
+            }
+        };
+
+        let items: Vec<_> = prefetch_metadata(event_stream_from_vector(events, 0), fetch, 3)
+            .collect()
+            .await;
+
+        let positions: Vec<_> = items
+            .iter()
+            .map(|(event, index, _)| (event.block_number, event.log_index, *index))
+            .collect();
+        let expected: Vec<_> = (0..16)
+            .map(|index| (index as u64 / 2, index as u64 % 2, index))
+            .collect();
+        assert_eq!(positions, expected);
+        for (event, _, metadata) in &items {
+            match event.log_index {
+                0 => assert_eq!(metadata, &Some(Ok(format!("doc{}", event.block_number)))),
+                _ => assert_eq!(metadata, &None),
+            }
+        }
+        assert!(max_in_flight.load(Ordering::SeqCst) > 1);
+        assert!(max_in_flight.load(Ordering::SeqCst) <= 3);
+    }
+
+    fn dummy_ipfs_getter(
+        _url: String,
+    ) -> Pin<Box<dyn futures::Future<Output = Result<String, MetadataError>> + Send>> {
+        Box::pin(async move { Ok(r#"{ "title": "Foo", "description": "Bar" }"#.to_string()) })
+    }
+
+    async fn event_stream_to_store(events: Vec<Event>) -> Store {
+        let mut store = Store::new();
+        let event_stream = event_stream_from_vector(events, 0);
+        pin_mut!(event_stream);
+
+        while let Some((event, _index)) = event_stream.next().await {
+            let change_set = event_to_changeset(&event, dummy_ipfs_getter).await;
+            store.write_changes(&event, &change_set.changes).unwrap();
+        }
+        store
     }
 
-    async fn event_stream_to_db_dump(events: Vec<Event>) -> Result<String, Error> {
-        let connection_string = "host=localhost user=postgres password=postgres";
-        let (mut client, connection) = tokio_postgres::connect(connection_string, NoTls).await?;
-        tokio::spawn(connection);
-        let transaction = client.transaction().await?;
-        transaction.batch_execute(DB_SCHEMA).await?;
+    async fn event_stream_to_db_dump(events: Vec<Event>) -> String {
+        let mut client = connect().await;
+        let transaction = transaction_with_schema(&mut client).await;
 
         let event_stream = event_stream_from_vector(events, 0);
         pin_mut!(event_stream);
 
         while let Some((event, _index)) = event_stream.next().await {
             let change_set = event_to_changeset(&event, dummy_ipfs_getter).await;
-            transaction.simple_query(&change_set.sql).await?;
+            transaction
+                .simple_query(&change_set.to_sql(Dialect::Postgres))
+                .await
+                .unwrap();
         }
 
         let rows = transaction
             .query("SELECT JSON_AGG(project) #>> '{}' FROM project;", &[])
-            .await?;
-        Ok(rows[0].get(0))
+            .await
+            .unwrap();
+        rows[0].get(0)
     }
 }
@@ -0,0 +1,358 @@
+use std::fmt;
+use std::time::Duration;
+
+use cid::Cid;
+
+use crate::config::IpfsConfig;
+use crate::event_handling::MetadataError;
+use crate::ipfs_cache::IpfsCache;
+use crate::ipfs_verify::verify_content;
+
+/// Longest wait between two rounds of requests to the gateways.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// Fetches IPFS content through HTTP gateways. Gateways are tried in order
+/// with a timeout on each request, so that a slow gateway only costs
+/// `timeout` before the next one is asked. When all gateways fail, they are
+/// tried again after an exponentially growing delay. With a cache, gateways
+/// are only asked for documents that are not cached yet. Content that does
+/// not match its CID counts as a failure of the gateway that served it.
+#[derive(Clone)]
+pub struct IpfsClient {
+    client: reqwest::Client,
+    cache: Option<IpfsCache>,
+    gateways: Vec<String>,
+    timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum GatewayError {
+    TimedOut,
+    Status(reqwest::StatusCode),
+    Request(String),
+    /// The gateway answered with content that is not what the CID refers to
+    InvalidContent(String),
+}
+
+impl fmt::Display for GatewayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GatewayError::TimedOut => write!(f, "timed out"),
+            GatewayError::Status(status) => write!(f, "HTTP {}", status),
+            GatewayError::Request(err) => write!(f, "{}", err),
+            GatewayError::InvalidContent(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<reqwest::Error> for GatewayError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            GatewayError::TimedOut
+        } else {
+            GatewayError::Request(err.to_string())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum IpfsError {
+    InvalidCid(String),
+    /// Every gateway failed on every attempt
+    Exhausted {
+        cid: String,
+        attempts: u32,
+        /// Last error of each gateway
+        errors: Vec<(String, GatewayError)>,
+    },
+}
+
+impl fmt::Display for IpfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpfsError::InvalidCid(cid) => write!(f, "{} is not a valid CID", cid),
+            IpfsError::Exhausted {
+                cid,
+                attempts,
+                errors,
+            } => {
+                write!(
+                    f,
+                    "cannot fetch {} from IPFS after {} attempts",
+                    cid, attempts
+                )?;
+                for (gateway, error) in errors {
+                    write!(f, "\n  - {}: {}", gateway, error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for IpfsError {}
+
+/// Content that does not match its CID is the fault of the gateway that
+/// served it, and another gateway may serve the right one later, so only a
+/// CID that cannot be parsed is beyond retrying.
+impl From<IpfsError> for MetadataError {
+    fn from(err: IpfsError) -> Self {
+        match err {
+            IpfsError::InvalidCid(_) => MetadataError::InvalidContent(err.to_string()),
+            IpfsError::Exhausted { .. } => MetadataError::Fetch(err.to_string()),
+        }
+    }
+}
+
+/// Checks fetched bytes against the CID, accepting content whose CID cannot
+/// be recomputed.
+fn verified_text(cid: &Cid, content: Vec<u8>) -> Result<String, String> {
+    verify_content(cid, &content)?;
+    String::from_utf8(content).map_err(|_| format!("content of {} is not UTF-8", cid))
+}
+
+impl IpfsClient {
+    pub fn new(config: &IpfsConfig) -> Self {
+        IpfsClient {
+            client: reqwest::Client::new(),
+            cache: None,
+            gateways: config.gateways.clone(),
+            timeout: Duration::from_millis(config.timeout_ms),
+            retries: config.retries,
+            backoff: Duration::from_millis(config.backoff_ms),
+        }
+    }
+
+    pub fn with_cache(mut self, cache: IpfsCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Fetches the document behind `cid`, verified against it when
+    /// possible. Content failing verification is never returned nor cached.
+    pub async fn get(&self, cid: &str) -> Result<String, IpfsError> {
+        let parsed = Cid::try_from(cid).map_err(|_| IpfsError::InvalidCid(cid.to_string()))?;
+        let Some(cache) = &self.cache else {
+            return self.get_from_gateways(cid, &parsed).await;
+        };
+        match cache.get(cid) {
+            // Entries written before verification existed are checked too.
+            Ok(Some(content)) if verify_content(&parsed, content.as_bytes()).is_ok() => {
+                return Ok(content)
+            }
+            Ok(_) => {}
+            Err(err) => eprintln!("Warning: cannot read {} from IPFS cache: {}", cid, err),
+        }
+        let content = self.get_from_gateways(cid, &parsed).await?;
+        if let Err(err) = cache.put(cid, &content) {
+            eprintln!("Warning: cannot write {} to IPFS cache: {}", cid, err);
+        }
+        Ok(content)
+    }
+
+    async fn get_from_gateways(&self, cid: &str, parsed: &Cid) -> Result<String, IpfsError> {
+        let mut errors: Vec<(String, GatewayError)> = vec![];
+        for round in 0..=self.retries {
+            if round > 0 {
+                tokio::time::sleep(retry_delay(self.backoff, round)).await;
+            }
+            for gateway in &self.gateways {
+                let result = self
+                    .get_from_gateway(gateway, cid)
+                    .await
+                    .and_then(|content| {
+                        verified_text(parsed, content).map_err(GatewayError::InvalidContent)
+                    });
+                match result {
+                    Ok(content) => return Ok(content),
+                    Err(error) => match errors.iter_mut().find(|(url, _)| url == gateway) {
+                        Some(entry) => entry.1 = error,
+                        None => errors.push((gateway.clone(), error)),
+                    },
+                }
+            }
+        }
+        Err(IpfsError::Exhausted {
+            cid: cid.to_string(),
+            attempts: self.retries + 1,
+            errors,
+        })
+    }
+
+    async fn get_from_gateway(&self, gateway: &str, cid: &str) -> Result<Vec<u8>, GatewayError> {
+        let response = self
+            .client
+            .get(format!("{}{}", gateway, cid))
+            .timeout(self.timeout)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(GatewayError::Status(response.status()));
+        }
+        // The timeout covers the body too, so a gateway trickling bytes
+        // cannot hold the stream either.
+        Ok(response.bytes().await?.to_vec())
+    }
+}
+
+/// Delay before the given round of retries, doubling from `backoff` on each
+/// round up to `MAX_RETRY_DELAY`.
+fn retry_delay(backoff: Duration, round: u32) -> Duration {
+    backoff
+        .saturating_mul(2u32.saturating_pow(round.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cid::multihash::Multihash;
+    use sha2::{Digest, Sha256};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    enum Reply {
+        Ok(&'static str),
+        Status(u16),
+        Hang,
+    }
+
+    /// Minimal HTTP gateway answering every request with `reply`. Returns
+    /// its base URL.
+    async fn serve(reply: Reply) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (status, body) = match reply {
+            Reply::Ok(body) => (200, Some(body)),
+            Reply::Status(status) => (status, Some("")),
+            Reply::Hang => (0, None),
+        };
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buffer = [0; 1024];
+                    let _ = socket.read(&mut buffer).await;
+                    match body {
+                        Some(body) => {
+                            let response = format!(
+                                "HTTP/1.1 {} X\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                                status,
+                                body.len(),
+                                body
+                            );
+                            let _ = socket.write_all(response.as_bytes()).await;
+                        }
+                        None => tokio::time::sleep(Duration::from_secs(60)).await,
+                    }
+                });
+            }
+        });
+        format!("http://{}/ipfs/", address)
+    }
+
+    fn client(gateways: Vec<String>) -> IpfsClient {
+        IpfsClient::new(&IpfsConfig {
+            gateways,
+            timeout_ms: 200,
+            retries: 1,
+            backoff_ms: 10,
+            ..Default::default()
+        })
+    }
+
+    fn raw_cid(content: &str) -> String {
+        Cid::new_v1(
+            0x55,
+            Multihash::wrap(0x12, &Sha256::digest(content.as_bytes())).unwrap(),
+        )
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_next_gateway() {
+        let hanging = serve(Reply::Hang).await;
+        let failing = serve(Reply::Status(502)).await;
+        let lying = serve(Reply::Ok(r#"{"title":"y"}"#)).await;
+        let working = serve(Reply::Ok(r#"{"title":"x"}"#)).await;
+
+        let content = client(vec![hanging, failing, lying, working])
+            .get(&raw_cid(r#"{"title":"x"}"#))
+            .await
+            .unwrap();
+        assert_eq!(content, r#"{"title":"x"}"#);
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let backoff = Duration::from_millis(500);
+        assert_eq!(retry_delay(backoff, 1), backoff);
+        assert_eq!(retry_delay(backoff, 3), Duration::from_secs(2));
+        assert_eq!(retry_delay(backoff, 40), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(Duration::MAX, 2), MAX_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_error_after_exhausting_gateways() {
+        let hanging = serve(Reply::Hang).await;
+        let failing = serve(Reply::Status(404)).await;
+
+        let err = client(vec![hanging.clone(), failing.clone()])
+            .get(&raw_cid("x"))
+            .await
+            .unwrap_err();
+        let IpfsError::Exhausted {
+            attempts, errors, ..
+        } = err
+        else {
+            panic!("gateways should be exhausted");
+        };
+        assert_eq!(attempts, 2);
+        assert_eq!(
+            errors,
+            vec![
+                (hanging, GatewayError::TimedOut),
+                (
+                    failing,
+                    GatewayError::Status(reqwest::StatusCode::NOT_FOUND)
+                )
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejects_content_not_matching_cid() {
+        let lying = serve(Reply::Ok("<html>pay up</html>")).await;
+        let cid = raw_cid(r#"{"title":"x"}"#);
+
+        let err = client(vec![lying]).get(&cid).await.unwrap_err();
+        assert!(matches!(
+            &err,
+            IpfsError::Exhausted { errors, .. }
+                if matches!(errors[0].1, GatewayError::InvalidContent(_))
+        ));
+        assert!(matches!(MetadataError::from(err), MetadataError::Fetch(_)));
+        let err = client(vec![]).get("bafy").await.unwrap_err();
+        assert!(matches!(err, IpfsError::InvalidCid(_)));
+        assert!(matches!(
+            MetadataError::from(err),
+            MetadataError::InvalidContent(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cache_is_consulted_first() {
+        let directory = tempfile::TempDir::new().unwrap();
+        let cache = IpfsCache::open(directory.path(), None).unwrap();
+        let (cached, fetched) = (raw_cid("cached"), raw_cid("fetched"));
+        cache.put(&cached, "cached").unwrap();
+        let working = serve(Reply::Ok("fetched")).await;
+        let ipfs = client(vec![working]).with_cache(cache.clone());
+
+        assert_eq!(ipfs.get(&cached).await.unwrap(), "cached");
+        assert_eq!(ipfs.get(&fetched).await.unwrap(), "fetched");
+        assert_eq!(cache.get(&fetched).unwrap(), Some("fetched".to_string()));
+    }
+}
@@ -0,0 +1,225 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tempfile::NamedTempFile;
+
+/// Directory of IPFS documents keyed by CID. Since content behind a CID never
+/// changes, entries never need to be refreshed; they are only evicted, least
+/// recently used first, when the cache grows past `max_size` bytes.
+#[derive(Clone)]
+pub struct IpfsCache {
+    directory: PathBuf,
+    max_size: Option<u64>,
+    /// Approximate size of the entries, to avoid listing the directory on
+    /// every write
+    size: Arc<AtomicU64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CacheStats {
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+struct CacheEntry {
+    path: PathBuf,
+    size: u64,
+    last_used: SystemTime,
+}
+
+/// CIDs only use base32/base58 characters; anything else (e.g. a full URL
+/// in a meta pointer) is not cached rather than turned into a path.
+fn is_valid_key(cid: &str) -> bool {
+    !cid.is_empty() && cid.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+impl IpfsCache {
+    pub fn open(directory: &Path, max_size: Option<u64>) -> io::Result<Self> {
+        fs::create_dir_all(directory)?;
+        let cache = IpfsCache {
+            directory: directory.to_path_buf(),
+            max_size,
+            size: Arc::new(AtomicU64::new(0)),
+        };
+        cache.size.store(cache.stats()?.bytes, Ordering::Relaxed);
+        Ok(cache)
+    }
+
+    fn entry_path(&self, cid: &str) -> Option<PathBuf> {
+        is_valid_key(cid).then(|| self.directory.join(cid))
+    }
+
+    pub fn get(&self, cid: &str) -> io::Result<Option<String>> {
+        let Some(path) = self.entry_path(cid) else {
+            return Ok(None);
+        };
+        match fs::read_to_string(&path) {
+            Ok(content) => {
+                // Recency for eviction; failing to record it is harmless.
+                let _ = File::options()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|file| file.set_modified(SystemTime::now()));
+                Ok(Some(content))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Stores `content` atomically, so that a concurrent reader or an
+    /// interrupted write never sees a partial document.
+    pub fn put(&self, cid: &str, content: &str) -> io::Result<()> {
+        let Some(path) = self.entry_path(cid) else {
+            return Ok(());
+        };
+        let mut file = NamedTempFile::new_in(&self.directory)?;
+        file.write_all(content.as_bytes())?;
+        file.persist(&path).map_err(|err| err.error)?;
+
+        let size =
+            self.size.fetch_add(content.len() as u64, Ordering::Relaxed) + content.len() as u64;
+        if let Some(max_size) = self.max_size {
+            if size > max_size {
+                self.prune(max_size)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn entries(&self) -> io::Result<Vec<CacheEntry>> {
+        let mut entries = vec![];
+        for dir_entry in fs::read_dir(&self.directory)? {
+            let dir_entry = dir_entry?;
+            let metadata = dir_entry.metadata()?;
+            let name = dir_entry.file_name();
+            if !metadata.is_file() || !name.to_str().is_some_and(is_valid_key) {
+                continue;
+            }
+            entries.push(CacheEntry {
+                path: dir_entry.path(),
+                size: metadata.len(),
+                last_used: metadata.modified()?,
+            });
+        }
+        Ok(entries)
+    }
+
+    pub fn stats(&self) -> io::Result<CacheStats> {
+        let entries = self.entries()?;
+        Ok(CacheStats {
+            entries: entries.len() as u64,
+            bytes: entries.iter().map(|entry| entry.size).sum(),
+        })
+    }
+
+    /// Evicts least recently used entries until the cache fits in
+    /// `max_size` bytes. Returns what was removed.
+    pub fn prune(&self, max_size: u64) -> io::Result<CacheStats> {
+        let mut entries = self.entries()?;
+        entries.sort_by_key(|entry| entry.last_used);
+        let mut size: u64 = entries.iter().map(|entry| entry.size).sum();
+        let mut removed = CacheStats {
+            entries: 0,
+            bytes: 0,
+        };
+        for entry in entries {
+            if size <= max_size {
+                break;
+            }
+            match fs::remove_file(&entry.path) {
+                Ok(()) => {}
+                // Evicted concurrently by another process
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+            size -= entry.size;
+            removed.entries += 1;
+            removed.bytes += entry.size;
+        }
+        self.size.store(size, Ordering::Relaxed);
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn age(cache: &IpfsCache, cid: &str, seconds: u64) {
+        File::options()
+            .write(true)
+            .open(cache.directory.join(cid))
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(seconds))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_get_and_put() {
+        let directory = TempDir::new().unwrap();
+        let cache = IpfsCache::open(directory.path(), None).unwrap();
+
+        assert_eq!(cache.get("bafyone").unwrap(), None);
+        cache.put("bafyone", r#"{"title":"one"}"#).unwrap();
+        assert_eq!(
+            cache.get("bafyone").unwrap(),
+            Some(r#"{"title":"one"}"#.to_string())
+        );
+
+        // Not a CID, never stored
+        cache.put("../escape", "x").unwrap();
+        assert_eq!(cache.get("../escape").unwrap(), None);
+
+        let reopened = IpfsCache::open(directory.path(), None).unwrap();
+        assert_eq!(
+            reopened.stats().unwrap(),
+            CacheStats {
+                entries: 1,
+                bytes: 15
+            }
+        );
+    }
+
+    #[test]
+    fn test_prune_evicts_least_recently_used() {
+        let directory = TempDir::new().unwrap();
+        let cache = IpfsCache::open(directory.path(), None).unwrap();
+        cache.put("old", "aaaa").unwrap();
+        cache.put("used", "bbbb").unwrap();
+        cache.put("new", "cccc").unwrap();
+        age(&cache, "old", 300);
+        age(&cache, "used", 200);
+        age(&cache, "new", 100);
+        cache.get("used").unwrap();
+
+        assert_eq!(
+            cache.prune(8).unwrap(),
+            CacheStats {
+                entries: 1,
+                bytes: 4
+            }
+        );
+        assert_eq!(cache.get("old").unwrap(), None);
+        assert!(cache.get("used").unwrap().is_some());
+        assert!(cache.get("new").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_put_enforces_max_size() {
+        let directory = TempDir::new().unwrap();
+        let cache = IpfsCache::open(directory.path(), Some(8)).unwrap();
+        cache.put("first", "aaaa").unwrap();
+        cache.put("second", "bbbb").unwrap();
+        age(&cache, "first", 100);
+        cache.put("third", "cccc").unwrap();
+
+        assert_eq!(cache.stats().unwrap().bytes, 8);
+        assert_eq!(cache.get("first").unwrap(), None);
+    }
+}
@@ -0,0 +1,415 @@
+use cid::Cid;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+
+use crate::event_handling::MetadataError;
+use crate::ipfs_verify::{verify_block, verify_content};
+
+const RAW_CODEC: u64 = 0x55;
+const DAG_PB_CODEC: u64 = 0x70;
+
+/// Starts CARv2 files, wrapping a CARv1 payload.
+const CARV2_PRAGMA: [u8; 11] = [
+    0x0a, 0xa1, 0x67, 0x76, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x02,
+];
+
+/// Resolves IPFS documents without network access, from a directory of
+/// files named after their CID (e.g. an IPFS cache directory) and/or from
+/// the blocks of CAR archives. The directory is consulted first. Files and
+/// blocks are verified against their CID.
+pub struct LocalIpfs {
+    directory: Option<PathBuf>,
+    blocks: HashMap<Cid, Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub enum LocalIpfsError {
+    InvalidCid(String),
+    NotFound(String),
+    /// A block is missing or cannot be decoded
+    Corrupt(String, String),
+    Io(String, io::Error),
+}
+
+impl fmt::Display for LocalIpfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocalIpfsError::InvalidCid(cid) => write!(f, "{} is not a valid CID", cid),
+            LocalIpfsError::NotFound(cid) => write!(f, "{} is not available locally", cid),
+            LocalIpfsError::Corrupt(cid, reason) => write!(f, "cannot decode {}: {}", cid, reason),
+            LocalIpfsError::Io(cid, err) => write!(f, "cannot read {}: {}", cid, err),
+        }
+    }
+}
+
+impl std::error::Error for LocalIpfsError {}
+
+/// Local files do not change between attempts, so only a missing document
+/// is worth looking for again.
+impl From<LocalIpfsError> for MetadataError {
+    fn from(err: LocalIpfsError) -> Self {
+        match err {
+            LocalIpfsError::InvalidCid(_) | LocalIpfsError::Corrupt(..) => {
+                MetadataError::InvalidContent(err.to_string())
+            }
+            LocalIpfsError::NotFound(_) | LocalIpfsError::Io(..) => {
+                MetadataError::Fetch(err.to_string())
+            }
+        }
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn read_varint(bytes: &[u8], position: &mut usize) -> Option<u64> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*position)?;
+        *position += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn read_slice<'a>(bytes: &'a [u8], position: &mut usize, length: u64) -> Option<&'a [u8]> {
+    let end = position.checked_add(usize::try_from(length).ok()?)?;
+    let slice = bytes.get(*position..end)?;
+    *position = end;
+    Some(slice)
+}
+
+/// Blocks of a CARv1 or CARv2 archive, keyed by CID.
+fn read_car(bytes: &[u8]) -> io::Result<Vec<(Cid, Vec<u8>)>> {
+    let bytes = if bytes.starts_with(&CARV2_PRAGMA) {
+        let header = &bytes[CARV2_PRAGMA.len()..];
+        let field = |offset: usize| -> io::Result<usize> {
+            let field = header
+                .get(offset..offset + 8)
+                .ok_or_else(|| invalid_data("truncated CARv2 header"))?;
+            usize::try_from(u64::from_le_bytes(field.try_into().unwrap()))
+                .map_err(|_| invalid_data("CARv2 payload too large"))
+        };
+        let (data_offset, data_size) = (field(16)?, field(24)?);
+        data_offset
+            .checked_add(data_size)
+            .and_then(|end| bytes.get(data_offset..end))
+            .ok_or_else(|| invalid_data("truncated CARv2 payload"))?
+    } else {
+        bytes
+    };
+
+    let mut position = 0;
+    let header_length =
+        read_varint(bytes, &mut position).ok_or_else(|| invalid_data("truncated CAR header"))?;
+    read_slice(bytes, &mut position, header_length)
+        .ok_or_else(|| invalid_data("truncated CAR header"))?;
+
+    let mut blocks = vec![];
+    while position < bytes.len() {
+        let section = read_varint(bytes, &mut position)
+            .and_then(|length| read_slice(bytes, &mut position, length))
+            .ok_or_else(|| invalid_data("truncated CAR block"))?;
+        let mut reader = Cursor::new(section);
+        let cid = Cid::read_bytes(&mut reader)
+            .map_err(|err| invalid_data(format!("invalid CID in CAR block: {}", err)))?;
+        blocks.push((cid, section[reader.position() as usize..].to_vec()));
+    }
+    Ok(blocks)
+}
+
+enum ProtobufValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// Fields of a protobuf message, in encoding order. Fixed-size fields are
+/// skipped since neither dag-pb nor UnixFS use them.
+fn protobuf_fields(bytes: &[u8]) -> Option<Vec<(u64, ProtobufValue<'_>)>> {
+    let mut fields = vec![];
+    let mut position = 0;
+    while position < bytes.len() {
+        let key = read_varint(bytes, &mut position)?;
+        let value = match key & 0x7 {
+            0 => ProtobufValue::Varint(read_varint(bytes, &mut position)?),
+            1 => {
+                read_slice(bytes, &mut position, 8)?;
+                continue;
+            }
+            2 => {
+                let length = read_varint(bytes, &mut position)?;
+                ProtobufValue::Bytes(read_slice(bytes, &mut position, length)?)
+            }
+            5 => {
+                read_slice(bytes, &mut position, 4)?;
+                continue;
+            }
+            _ => return None,
+        };
+        fields.push((key >> 3, value));
+    }
+    Some(fields)
+}
+
+/// Links and UnixFS payload of a dag-pb node.
+fn decode_dag_pb(block: &[u8]) -> Result<(Vec<Cid>, Vec<u8>), String> {
+    let mut links = vec![];
+    let mut unixfs = None;
+    for (field, value) in protobuf_fields(block).ok_or("invalid dag-pb node")? {
+        match (field, value) {
+            (1, ProtobufValue::Bytes(data)) => unixfs = Some(data),
+            (2, ProtobufValue::Bytes(link)) => {
+                for (field, value) in protobuf_fields(link).ok_or("invalid dag-pb link")? {
+                    if let (1, ProtobufValue::Bytes(hash)) = (field, value) {
+                        links.push(Cid::try_from(hash).map_err(|err| err.to_string())?);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut data = vec![];
+    let mut kind = None;
+    for (field, value) in
+        protobuf_fields(unixfs.unwrap_or_default()).ok_or("invalid UnixFS data")?
+    {
+        match (field, value) {
+            (1, ProtobufValue::Varint(value)) => kind = Some(value),
+            (2, ProtobufValue::Bytes(bytes)) => data = bytes.to_vec(),
+            _ => {}
+        }
+    }
+    // 0 is raw, 2 is file
+    match kind {
+        Some(0) | Some(2) => Ok((links, data)),
+        Some(kind) => Err(format!("UnixFS node of type {} is not a file", kind)),
+        None => Err("missing UnixFS type".to_string()),
+    }
+}
+
+impl LocalIpfs {
+    pub fn open(directory: Option<&Path>, car_files: &[PathBuf]) -> io::Result<Self> {
+        let mut blocks = HashMap::new();
+        for path in car_files {
+            let car = read_car(&fs::read(path)?).map_err(|err| {
+                io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+            })?;
+            for (cid, block) in car {
+                // Dropped blocks make the documents using them unavailable
+                // rather than silently wrong.
+                match verify_block(&cid, &block) {
+                    Ok(_) => {
+                        blocks.insert(cid, block);
+                    }
+                    Err(err) => eprintln!("Warning: skipping block in {}: {}", path.display(), err),
+                }
+            }
+        }
+        Ok(LocalIpfs {
+            directory: directory.map(Path::to_path_buf),
+            blocks,
+        })
+    }
+
+    pub fn get(&self, cid: &str) -> Result<String, LocalIpfsError> {
+        let parsed = Cid::try_from(cid).map_err(|_| LocalIpfsError::InvalidCid(cid.to_string()))?;
+
+        if let Some(directory) = &self.directory {
+            // Parsing first guarantees the name cannot escape the directory.
+            match fs::read(directory.join(cid)) {
+                Ok(content) => {
+                    verify_content(&parsed, &content)
+                        .map_err(|err| LocalIpfsError::Corrupt(cid.to_string(), err))?;
+                    return String::from_utf8(content).map_err(|_| {
+                        LocalIpfsError::Corrupt(cid.to_string(), "not UTF-8".to_string())
+                    });
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(LocalIpfsError::Io(cid.to_string(), err)),
+            }
+        }
+
+        if !self.blocks.contains_key(&parsed) {
+            return Err(LocalIpfsError::NotFound(cid.to_string()));
+        }
+        let content = self
+            .file_content(&parsed)
+            .map_err(|reason| LocalIpfsError::Corrupt(cid.to_string(), reason))?;
+        String::from_utf8(content)
+            .map_err(|_| LocalIpfsError::Corrupt(cid.to_string(), "not UTF-8".to_string()))
+    }
+
+    /// Reassembles a UnixFS file from its blocks.
+    fn file_content(&self, cid: &Cid) -> Result<Vec<u8>, String> {
+        let block = self
+            .blocks
+            .get(cid)
+            .ok_or_else(|| format!("block {} is missing", cid))?;
+        match cid.codec() {
+            RAW_CODEC => Ok(block.clone()),
+            DAG_PB_CODEC => {
+                let (links, mut content) = decode_dag_pb(block)?;
+                for link in links {
+                    content.extend(self.file_content(&link)?);
+                }
+                Ok(content)
+            }
+            codec => Err(format!("unsupported codec {:#x}", codec)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cid::multihash::Multihash;
+    use sha2::{Digest, Sha256};
+    use tempfile::TempDir;
+
+    fn varint(mut value: u64) -> Vec<u8> {
+        let mut bytes = vec![];
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    fn bytes_field(field: u64, bytes: &[u8]) -> Vec<u8> {
+        [
+            varint(field << 3 | 2),
+            varint(bytes.len() as u64),
+            bytes.to_vec(),
+        ]
+        .concat()
+    }
+
+    fn sha256(block: &[u8]) -> Multihash<64> {
+        Multihash::wrap(0x12, &Sha256::digest(block)).unwrap()
+    }
+
+    /// dag-pb UnixFS file node holding `data` and linking to `links`.
+    fn file_node(data: &[u8], links: &[Cid]) -> Vec<u8> {
+        let unixfs = [vec![0x08, 0x02], bytes_field(2, data)].concat();
+        let links = links
+            .iter()
+            .flat_map(|link| bytes_field(2, &bytes_field(1, &link.to_bytes())));
+        [links.collect(), bytes_field(1, &unixfs)].concat()
+    }
+
+    fn car(blocks: &[(Cid, Vec<u8>)]) -> Vec<u8> {
+        // {"roots": [], "version": 1} in dag-cbor
+        let header = [
+            0xa2, 0x65, b'r', b'o', b'o', b't', b's', 0x80, 0x67, b'v', b'e', b'r', b's', b'i',
+            b'o', b'n', 0x01,
+        ];
+        let mut car = [varint(header.len() as u64), header.to_vec()].concat();
+        for (cid, block) in blocks {
+            let section = [cid.to_bytes(), block.clone()].concat();
+            car.extend(varint(section.len() as u64));
+            car.extend(section);
+        }
+        car
+    }
+
+    #[test]
+    fn test_resolve_from_car() {
+        let raw = br#"{"title":"raw"}"#.to_vec();
+        let raw_cid = Cid::new_v1(RAW_CODEC, sha256(&raw));
+
+        let single = file_node(br#"{"title":"single"}"#, &[]);
+        let single_cid = Cid::new_v0(sha256(&single)).unwrap();
+
+        let chunks = [br#"{"title":"#.to_vec(), br#""chunked"}"#.to_vec()];
+        let chunk_cids: Vec<Cid> = chunks
+            .iter()
+            .map(|chunk| Cid::new_v1(RAW_CODEC, sha256(chunk)))
+            .collect();
+        let root = file_node(b"", &chunk_cids);
+        let root_cid = Cid::new_v1(DAG_PB_CODEC, sha256(&root));
+
+        let tampered_cid = Cid::new_v1(RAW_CODEC, sha256(b"original"));
+
+        let directory = TempDir::new().unwrap();
+        let car_path = directory.path().join("docs.car");
+        fs::write(
+            &car_path,
+            car(&[
+                (raw_cid, raw),
+                (single_cid, single),
+                (root_cid, root),
+                (chunk_cids[0], chunks[0].clone()),
+                (chunk_cids[1], chunks[1].clone()),
+                (tampered_cid, b"tampered".to_vec()),
+            ]),
+        )
+        .unwrap();
+
+        let ipfs = LocalIpfs::open(None, &[car_path]).unwrap();
+        assert_eq!(
+            ipfs.get(&raw_cid.to_string()).unwrap(),
+            r#"{"title":"raw"}"#
+        );
+        assert!(single_cid.to_string().starts_with("Qm"));
+        assert_eq!(
+            ipfs.get(&single_cid.to_string()).unwrap(),
+            r#"{"title":"single"}"#
+        );
+        assert_eq!(
+            ipfs.get(&root_cid.to_string()).unwrap(),
+            r#"{"title":"chunked"}"#
+        );
+        assert!(matches!(
+            ipfs.get(&Cid::new_v1(RAW_CODEC, sha256(b"other")).to_string()),
+            Err(LocalIpfsError::NotFound(_))
+        ));
+        assert!(matches!(
+            ipfs.get(&tampered_cid.to_string()),
+            Err(LocalIpfsError::NotFound(_))
+        ));
+        assert!(matches!(
+            ipfs.get("not-a-cid"),
+            Err(LocalIpfsError::InvalidCid(_))
+        ));
+    }
+
+    #[test]
+    fn test_resolve_from_directory() {
+        let directory = TempDir::new().unwrap();
+        let cid = Cid::new_v1(RAW_CODEC, sha256(b"{}")).to_string();
+        fs::write(directory.path().join(&cid), "{}").unwrap();
+
+        let tampered = Cid::new_v1(RAW_CODEC, sha256(b"[]")).to_string();
+        fs::write(directory.path().join(&tampered), "{}").unwrap();
+
+        let ipfs = LocalIpfs::open(Some(directory.path()), &[]).unwrap();
+        assert_eq!(ipfs.get(&cid).unwrap(), "{}");
+        assert!(matches!(
+            ipfs.get(&tampered),
+            Err(LocalIpfsError::Corrupt(..))
+        ));
+        assert!(matches!(
+            MetadataError::from(ipfs.get(&tampered).unwrap_err()),
+            MetadataError::InvalidContent(_)
+        ));
+        assert!(matches!(
+            MetadataError::from(
+                ipfs.get(&Cid::new_v1(RAW_CODEC, sha256(b"null")).to_string())
+                    .unwrap_err()
+            ),
+            MetadataError::Fetch(_)
+        ));
+    }
+}
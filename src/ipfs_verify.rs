@@ -0,0 +1,105 @@
+use cid::Cid;
+use sha2::{Digest, Sha256};
+
+const SHA2_256: u64 = 0x12;
+const RAW_CODEC: u64 = 0x55;
+const DAG_PB_CODEC: u64 = 0x70;
+
+/// Files up to this size are stored in a single dag-pb block by default.
+const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub enum Verification {
+    Verified,
+    /// The CID cannot be recomputed from the content alone, e.g. a file
+    /// split over several blocks
+    Unverifiable(&'static str),
+}
+
+fn sha256_matches(cid: &Cid, bytes: &[u8]) -> Result<Verification, String> {
+    if cid.hash().code() != SHA2_256 {
+        return Ok(Verification::Unverifiable("hash function is not sha2-256"));
+    }
+    if cid.hash().digest() == Sha256::digest(bytes).as_slice() {
+        Ok(Verification::Verified)
+    } else {
+        Err(format!("content does not match {}", cid))
+    }
+}
+
+fn varint(mut value: u64, bytes: &mut Vec<u8>) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// dag-pb node holding `content` as a single-block UnixFS file, encoded the
+/// way `ipfs add` does.
+fn unixfs_file_block(content: &[u8]) -> Vec<u8> {
+    let mut unixfs = vec![0x08, 0x02];
+    if !content.is_empty() {
+        unixfs.push(0x12);
+        varint(content.len() as u64, &mut unixfs);
+        unixfs.extend(content);
+    }
+    unixfs.push(0x18);
+    varint(content.len() as u64, &mut unixfs);
+
+    let mut block = vec![0x0a];
+    varint(unixfs.len() as u64, &mut block);
+    block.extend(unixfs);
+    block
+}
+
+/// Checks that the file `content` is what `cid` refers to.
+pub fn verify_content(cid: &Cid, content: &[u8]) -> Result<Verification, String> {
+    match cid.codec() {
+        RAW_CODEC => sha256_matches(cid, content),
+        DAG_PB_CODEC if content.len() <= DEFAULT_CHUNK_SIZE => {
+            sha256_matches(cid, &unixfs_file_block(content))
+        }
+        DAG_PB_CODEC => Ok(Verification::Unverifiable("file spans several blocks")),
+        _ => Ok(Verification::Unverifiable("unsupported codec")),
+    }
+}
+
+/// Checks that a block, as stored in a CAR archive, is what `cid` refers to.
+pub fn verify_block(cid: &Cid, block: &[u8]) -> Result<Verification, String> {
+    sha256_matches(cid, block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cid(cid: &str) -> Cid {
+        cid.parse().unwrap()
+    }
+
+    #[test]
+    fn test_verify_content() {
+        // `echo "hello world" | ipfs add`, with and without --raw-leaves
+        let v0 = cid("QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o");
+        let raw = cid("bafkreifjjcie6lypi6ny7amxnfftagclbuxndqonfipmb64f2km2devei4");
+        let v1 = Cid::new_v1(DAG_PB_CODEC, *v0.hash());
+
+        for cid in [v0, raw, v1] {
+            assert_eq!(
+                verify_content(&cid, b"hello world\n"),
+                Ok(Verification::Verified)
+            );
+            assert!(verify_content(&cid, b"hello world").is_err());
+        }
+        // `ipfs add` of an empty file
+        assert_eq!(
+            verify_content(&cid("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH"), b""),
+            Ok(Verification::Verified)
+        );
+        assert!(matches!(
+            verify_content(&v0, &vec![b' '; DEFAULT_CHUNK_SIZE + 1]),
+            Ok(Verification::Unverifiable(_))
+        ));
+    }
+}
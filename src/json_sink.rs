@@ -0,0 +1,238 @@
+use serde_json::{json, Map};
+use std::io::{self, Write};
+
+use crate::changes::{Change, Row, Value};
+use crate::event_handling::Event;
+use crate::sink::Sink;
+
+/// Writes one JSON record per event, with the event's position in its chain
+/// and the changes it makes to the tables, for consumers that want entity
+/// changes rather than SQL:
+///
+/// ```json
+/// {"chainId":1,"blockNumber":2,"logIndex":0,"changes":[{"table":"project","operation":"add_to_list","key":{...},"column":"owners","item":"0x..."}]}
+/// ```
+///
+/// uint256 values are decimal strings, since they do not fit in JSON numbers
+/// as most parsers read them.
+pub struct JsonSink<W: Write> {
+    writer: W,
+}
+
+pub fn json_value(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer(value) => json!(value),
+        Value::Numeric(value) => json!(value.to_string()),
+        Value::Text(value) => json!(value),
+        Value::Json(value) => serde_json::from_str(value).unwrap_or_else(|_| json!(value)),
+        Value::Search(texts) => json!(texts),
+    }
+}
+
+fn json_row(row: &Row) -> serde_json::Value {
+    serde_json::Value::Object(
+        row.iter()
+            .map(|(column, value)| (column.to_string(), json_value(value)))
+            .collect::<Map<_, _>>(),
+    )
+}
+
+fn json_change(change: &Change) -> serde_json::Value {
+    match change {
+        Change::Insert { table, values } => json!({
+            "table": table.to_string(),
+            "operation": "insert",
+            "values": json_row(values),
+        }),
+        Change::Update { table, key, values } => json!({
+            "table": table.to_string(),
+            "operation": "update",
+            "key": json_row(key),
+            "values": json_row(values),
+        }),
+        Change::Upsert { table, key, values } => json!({
+            "table": table.to_string(),
+            "operation": "upsert",
+            "key": json_row(key),
+            "values": json_row(values),
+        }),
+        Change::Delete { table, key } => json!({
+            "table": table.to_string(),
+            "operation": "delete",
+            "key": json_row(key),
+        }),
+        Change::AddToList {
+            table,
+            key,
+            column,
+            item,
+        } => json!({
+            "table": table.to_string(),
+            "operation": "add_to_list",
+            "key": json_row(key),
+            "column": column.to_string(),
+            "item": item,
+        }),
+        Change::RemoveFromList {
+            table,
+            key,
+            column,
+            item,
+        } => json!({
+            "table": table.to_string(),
+            "operation": "remove_from_list",
+            "key": json_row(key),
+            "column": column.to_string(),
+            "item": item,
+        }),
+    }
+}
+
+impl<W: Write> JsonSink<W> {
+    pub fn new(writer: W) -> Self {
+        JsonSink { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Sink for JsonSink<W> {
+    /// Consumers own their storage, so there is no schema to write.
+    fn write_schema(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_changes(&mut self, event: &Event, changes: &[Change]) -> io::Result<()> {
+        let record = json!({
+            "chainId": event.chain_id,
+            "blockNumber": event.block_number,
+            "logIndex": event.log_index,
+            "changes": changes.iter().map(json_change).collect::<Vec<_>>(),
+        });
+        writeln!(self.writer, "{}", record)
+    }
+
+    /// Records have no transactions, so they are written in a single write,
+    /// which a consumer only sees in part if writing fails midway. Records
+    /// written again are recognized by their chain, block and log index.
+    fn write_atomically(&mut self, change_sets: &[(Event, Vec<Change>)]) -> io::Result<()> {
+        let mut records = vec![];
+        {
+            let mut sink = JsonSink::new(&mut records);
+            for (event, changes) in change_sets {
+                sink.write_changes(event, changes)?;
+            }
+        }
+        self.writer.write_all(&records)
+    }
+
+    fn is_in_batch(&self) -> bool {
+        false
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handling::{event_to_changeset_with_metadata, EventPayload, MetaPtr};
+    use crate::test_helpers;
+    use ethers::types::{Address, U256};
+
+    /// An event whose chain id and log index are not the defaults, to
+    /// check that they are passed through.
+    fn event(block_number: u64, data: EventPayload) -> Event {
+        Event {
+            chain_id: 10,
+            log_index: 3,
+            ..test_helpers::event(block_number, data)
+        }
+    }
+
+    #[test]
+    fn test_change_records() {
+        let mut sink = JsonSink::new(vec![]);
+        let project_id = U256::from(7);
+        let events = [
+            (event(1, EventPayload::ProjectCreated { project_id }), None),
+            (
+                event(
+                    2,
+                    EventPayload::MetadataUpdated {
+                        project_id,
+                        meta_ptr: MetaPtr {
+                            pointer: "cid".to_string(),
+                        },
+                    },
+                ),
+                Some(Ok(r#"{"title":"Foo","description":"Bar"}"#.to_string())),
+            ),
+            (
+                event(
+                    3,
+                    EventPayload::OwnerAdded {
+                        project_id,
+                        owner: Address::from_low_u64_be(0xa),
+                    },
+                ),
+                None,
+            ),
+        ];
+        for (event, metadata) in events {
+            let change_set = event_to_changeset_with_metadata(&event, metadata);
+            sink.write_changes(&event, &change_set.changes).unwrap();
+        }
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        let records: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 3);
+
+        assert_eq!(
+            records[0],
+            json!({
+                "chainId": 10,
+                "blockNumber": 1,
+                "logIndex": 3,
+                "changes": [{
+                    "table": "project",
+                    "operation": "insert",
+                    "values": { "chain_id": 10, "project_id": "7", "created_at_block": 1 },
+                }],
+            })
+        );
+
+        let update = &records[1]["changes"][0];
+        assert_eq!(update["operation"], "update");
+        assert_eq!(update["key"], json!({ "chain_id": 10, "project_id": "7" }));
+        assert_eq!(
+            update["values"]["metadata"],
+            json!({ "title": "Foo", "description": "Bar" })
+        );
+        assert_eq!(update["values"]["metadata_status"], "ok");
+        assert_eq!(update["values"]["search"], json!(["Foo", "Bar"]));
+        assert_eq!(records[1]["changes"][1]["operation"], "delete");
+
+        assert_eq!(
+            records[2]["changes"][0],
+            json!({
+                "table": "project",
+                "operation": "add_to_list",
+                "key": { "chain_id": 10, "project_id": "7" },
+                "column": "owners",
+                "item": "0x000000000000000000000000000000000000000a",
+            })
+        );
+    }
+}
@@ -0,0 +1,264 @@
+//! HTTP endpoint for `gs-index listen`, taking batches of events pushed by
+//! remote producers instead of reading them from stdin.
+
+use futures::{Future, StreamExt};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::json;
+use std::convert::Infallible;
+use std::io;
+use std::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::event_handling::{event_to_changeset_with_metadata, Event, MetadataError};
+use crate::event_source::event_stream_from_vector;
+use crate::indexer::prefetch_metadata;
+use crate::server::read_body;
+use crate::sink::Sink;
+
+/// Largest request read. Producers split larger batches.
+const MAX_BODY_SIZE: usize = 16 << 20;
+
+/// The events of a request, and where to report the number applied.
+pub struct Batch {
+    events: Vec<Event>,
+    applied: oneshot::Sender<Result<usize, Rejected>>,
+}
+
+/// Why a batch was not applied.
+pub enum Rejected {
+    /// The sink refused the changes and rolled them back, for example
+    /// because the batch creates a project that exists already.
+    Conflict(String),
+    /// The sink failed, and no more batches are applied.
+    Failed(String),
+}
+
+/// The events of an NDJSON body, or why the line that is not one cannot be
+/// parsed. Remote input is never skipped silently nor allowed to stop
+/// indexing, as `errors.on_parse_error` would.
+fn parse_batch(body: &[u8]) -> Result<Vec<Event>, String> {
+    let text = std::str::from_utf8(body).map_err(|err| err.to_string())?;
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|err| format!("line {}: {}", index + 1, err))
+        })
+        .collect()
+}
+
+/// Passes the bodies POSTed to `/events` to `batches`, and answers once
+/// they have been applied, until the process exits.
+pub async fn listen(listener: TcpListener, batches: mpsc::Sender<Batch>) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let make_service = make_service_fn(move |_connection| {
+        let batches = batches.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let batches = batches.clone();
+                async move { Ok::<_, Infallible>(handle(&batches, request).await) }
+            }))
+        }
+    });
+    Server::from_tcp(listener)
+        .map_err(io::Error::other)?
+        .serve(make_service)
+        .await
+        .map_err(io::Error::other)
+}
+
+async fn handle(batches: &mpsc::Sender<Batch>, request: Request<Body>) -> Response<Body> {
+    if (request.method(), request.uri().path()) != (&Method::POST, "/events") {
+        return text(StatusCode::NOT_FOUND, "not found");
+    }
+    let body = match read_body(request.into_body(), MAX_BODY_SIZE).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    // Nothing is applied unless every line is an event
+    let events = match parse_batch(&body) {
+        Ok(events) => events,
+        Err(err) => return text(StatusCode::BAD_REQUEST, &err),
+    };
+    let (applied, result) = oneshot::channel();
+    if batches.send(Batch { events, applied }).await.is_err() {
+        return text(StatusCode::SERVICE_UNAVAILABLE, "not indexing anymore");
+    }
+    match result.await {
+        Ok(Ok(events)) => Response::builder()
+            .header("content-type", "application/json")
+            .body(Body::from(json!({ "applied": events }).to_string()))
+            .expect("valid response"),
+        Ok(Err(Rejected::Conflict(err))) => text(StatusCode::CONFLICT, &err),
+        Ok(Err(Rejected::Failed(err))) => text(StatusCode::INTERNAL_SERVER_ERROR, &err),
+        Err(_) => text(StatusCode::SERVICE_UNAVAILABLE, "not indexing anymore"),
+    }
+}
+
+fn text(status: StatusCode, body: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "text/plain; charset=utf-8")
+        .body(Body::from(body.to_string()))
+        .expect("valid response")
+}
+
+/// Applies the batches one at a time, each in a transaction of its own, and
+/// reports each one once its changes have been committed. Batches the sink
+/// refuses and rolls back are reported and skipped; any other error of the
+/// sink stops indexing, after being reported.
+pub async fn apply_batches<F, Fut>(
+    mut batches: mpsc::Receiver<Batch>,
+    sink: &mut dyn Sink,
+    fetch: F,
+    concurrency: usize,
+) -> io::Result<()>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<String, MetadataError>>,
+{
+    while let Some(batch) = batches.recv().await {
+        let applied = batch.events.len();
+        let change_sets: Vec<(Event, _)> = prefetch_metadata(
+            event_stream_from_vector(batch.events, 0),
+            &fetch,
+            concurrency,
+        )
+        .map(|(event, _index, metadata)| {
+            let change_set = event_to_changeset_with_metadata(&event, metadata);
+            (event, change_set.changes)
+        })
+        .collect()
+        .await;
+        let result = sink
+            .write_atomically(&change_sets)
+            .and_then(|()| sink.finish());
+        let (report, result) = match result {
+            Ok(()) => (Ok(applied), Ok(())),
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                (Err(Rejected::Conflict(err.to_string())), Ok(()))
+            }
+            Err(err) => (Err(Rejected::Failed(err.to_string())), Err(err)),
+        };
+        // The producer may have given up waiting, which changes nothing.
+        let _ = batch.applied.send(report);
+        result?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::Value;
+    use crate::event_handling::EventPayload;
+    use crate::store::Store;
+    use crate::test_helpers::event;
+    use ethers::types::U256;
+
+    fn event_line(block_number: u64, project_id: u64) -> String {
+        serde_json::to_string(&event(
+            block_number,
+            EventPayload::ProjectCreated {
+                project_id: U256::from(project_id),
+            },
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_listen() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel(1);
+        tokio::spawn(listen(listener, sender));
+
+        let mut store = Store::new();
+        let http = reqwest::Client::new();
+        let requests = async {
+            let body = [event_line(1, 1), "not json".to_string(), event_line(2, 2)].join("\n");
+            let response = http
+                .post(format!("{}/events", url))
+                .body(body)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 400);
+            assert!(response.text().await.unwrap().starts_with("line 2: "));
+
+            let body = [event_line(1, 1), event_line(2, 2)].join("\n") + "\n";
+            let response = http
+                .post(format!("{}/events", url))
+                .body(body)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 200);
+            assert_eq!(
+                response.json::<serde_json::Value>().await.unwrap(),
+                json!({ "applied": 2 })
+            );
+
+            // The store refuses to create a project twice, and the project
+            // created before in the same batch is rolled back
+            let response = http
+                .post(format!("{}/events", url))
+                .body([event_line(3, 3), event_line(4, 1)].join("\n"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 409);
+
+            // Indexing goes on
+            let response = http
+                .post(format!("{}/events", url))
+                .body(event_line(5, 3))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 200);
+        };
+        let fetch = |_cid: String| async { Err(MetadataError::Fetch("offline".to_string())) };
+        let applying = apply_batches(receiver, &mut store, fetch, 1);
+        tokio::select! {
+            result = applying => panic!("stopped applying batches: {:?}", result),
+            () = requests => {}
+        }
+        let project_ids: Vec<&Value> = store
+            .rows("project")
+            .map(|project| &project["project_id"])
+            .collect();
+        assert_eq!(
+            project_ids,
+            [
+                &Value::Numeric(U256::one()),
+                &Value::Numeric(U256::from(2)),
+                &Value::Numeric(U256::from(3))
+            ]
+        );
+
+        let response = reqwest::get(format!("{}/events", url)).await.unwrap();
+        assert_eq!(response.status(), 404);
+        let response = http
+            .post(format!("{}/events", url))
+            .body(vec![0xff, b'\n'])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        let response = http
+            .post(format!("{}/events", url))
+            .body(vec![b'\n'; MAX_BODY_SIZE + 1])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 413);
+        let response = http
+            .post(format!("{}/events", url))
+            .body(event_line(4, 4))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 503);
+    }
+}
@@ -0,0 +1,249 @@
+use ethers::contract::{parse_log, EthEvent};
+use ethers::types::{Log, H256};
+use std::fmt;
+
+use crate::event_handling::{Event, EventPayload, MetaPtr};
+
+use self::bindings::project_registry::{
+    MetadataUpdatedFilter, OwnerAddedFilter, OwnerRemovedFilter, ProjectCreatedFilter,
+};
+use self::bindings::round_factory::RoundCreatedFilter;
+use self::bindings::round_implementation::NewProjectApplicationFilter;
+
+// Each contract gets its own module since the generated `MetaPtr` structs
+// would otherwise clash.
+mod bindings {
+    pub mod project_registry {
+        ethers::contract::abigen!(ProjectRegistry, "abis/ProjectRegistry.json");
+    }
+
+    pub mod round_factory {
+        ethers::contract::abigen!(RoundFactory, "abis/RoundFactory.json");
+    }
+
+    pub mod round_implementation {
+        ethers::contract::abigen!(RoundImplementation, "abis/RoundImplementation.json");
+    }
+}
+
+#[derive(Debug)]
+pub enum LogDecodingError {
+    MissingField(&'static str),
+    Abi(ethers::abi::Error),
+}
+
+impl fmt::Display for LogDecodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogDecodingError::MissingField(field) => write!(f, "log has no {}", field),
+            LogDecodingError::Abi(err) => write!(f, "cannot decode log: {}", err),
+        }
+    }
+}
+
+impl From<ethers::abi::Error> for LogDecodingError {
+    fn from(err: ethers::abi::Error) -> Self {
+        LogDecodingError::Abi(err)
+    }
+}
+
+/// Topics of the events that `decode_log` understands, e.g. to narrow down a
+/// log filter.
+pub fn supported_event_signatures() -> Vec<H256> {
+    vec![
+        ProjectCreatedFilter::signature(),
+        MetadataUpdatedFilter::signature(),
+        OwnerAddedFilter::signature(),
+        OwnerRemovedFilter::signature(),
+        RoundCreatedFilter::signature(),
+        NewProjectApplicationFilter::signature(),
+    ]
+}
+
+/// Decodes a raw log into an `Event`. Logs of events that are not (yet)
+/// supported decode to `None`.
+pub fn decode_log(chain_id: u64, log: &Log) -> Result<Option<Event>, LogDecodingError> {
+    let Some(topic) = log.topics.first() else {
+        return Ok(None);
+    };
+
+    let data = if *topic == ProjectCreatedFilter::signature() {
+        let decoded: ProjectCreatedFilter = parse_log(log.clone())?;
+        EventPayload::ProjectCreated {
+            project_id: decoded.project_id,
+        }
+    } else if *topic == MetadataUpdatedFilter::signature() {
+        let decoded: MetadataUpdatedFilter = parse_log(log.clone())?;
+        EventPayload::MetadataUpdated {
+            project_id: decoded.project_id,
+            meta_ptr: MetaPtr {
+                pointer: decoded.meta_ptr.pointer,
+            },
+        }
+    } else if *topic == OwnerAddedFilter::signature() {
+        let decoded: OwnerAddedFilter = parse_log(log.clone())?;
+        EventPayload::OwnerAdded {
+            project_id: decoded.project_id,
+            owner: decoded.owner,
+        }
+    } else if *topic == OwnerRemovedFilter::signature() {
+        let decoded: OwnerRemovedFilter = parse_log(log.clone())?;
+        EventPayload::OwnerRemoved {
+            project_id: decoded.project_id,
+            owner: decoded.owner,
+        }
+    } else if *topic == RoundCreatedFilter::signature() {
+        let decoded: RoundCreatedFilter = parse_log(log.clone())?;
+        EventPayload::RoundCreated {
+            round_address: decoded.round_address,
+        }
+    } else if *topic == NewProjectApplicationFilter::signature() {
+        let decoded: NewProjectApplicationFilter = parse_log(log.clone())?;
+        EventPayload::NewProjectApplication {
+            project_id: H256::from(decoded.project_id),
+            application_index: decoded.application_index,
+        }
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some(Event {
+        chain_id,
+        address: log.address,
+        block_number: log
+            .block_number
+            .ok_or(LogDecodingError::MissingField("blockNumber"))?
+            .as_u64(),
+        log_index: log
+            .log_index
+            .ok_or(LogDecodingError::MissingField("logIndex"))?
+            .as_u64(),
+        data,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{encode, Token};
+    use ethers::types::{Address, U256};
+
+    fn make_log(topics: Vec<H256>, data: Vec<Token>) -> Log {
+        Log {
+            address: Address::from_low_u64_be(0x123),
+            topics,
+            data: encode(&data).into(),
+            block_number: Some(4242.into()),
+            log_index: Some(1.into()),
+            ..Default::default()
+        }
+    }
+
+    fn meta_ptr_token(pointer: &str) -> Token {
+        Token::Tuple(vec![
+            Token::Uint(U256::one()),
+            Token::String(pointer.to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_decode_project_created() {
+        let log = make_log(
+            vec![
+                ProjectCreatedFilter::signature(),
+                H256::from_low_u64_be(123),
+                H256::from(Address::from_low_u64_be(0x456)),
+            ],
+            vec![],
+        );
+
+        let event = decode_log(1, &log).unwrap().unwrap();
+        assert_eq!(event.chain_id, 1);
+        assert_eq!(event.address, Address::from_low_u64_be(0x123));
+        assert_eq!(event.block_number, 4242);
+        assert_eq!(event.log_index, 1);
+        assert!(matches!(
+            event.data,
+            EventPayload::ProjectCreated { project_id } if project_id == U256::from(123)
+        ));
+    }
+
+    #[test]
+    fn test_decode_metadata_updated() {
+        let log = make_log(
+            vec![
+                MetadataUpdatedFilter::signature(),
+                H256::from_low_u64_be(123),
+            ],
+            vec![meta_ptr_token("bafkreih")],
+        );
+
+        let event = decode_log(1, &log).unwrap().unwrap();
+        assert!(matches!(
+            event.data,
+            EventPayload::MetadataUpdated { project_id, meta_ptr } if project_id == U256::from(123) && meta_ptr.pointer == "bafkreih"
+        ));
+    }
+
+    #[test]
+    fn test_decode_round_created() {
+        let log = make_log(
+            vec![
+                RoundCreatedFilter::signature(),
+                H256::from(Address::from_low_u64_be(0x456)),
+                H256::from(Address::from_low_u64_be(0x789)),
+                H256::from(Address::from_low_u64_be(0xabc)),
+            ],
+            vec![],
+        );
+
+        let event = decode_log(1, &log).unwrap().unwrap();
+        assert!(matches!(
+            event.data,
+            EventPayload::RoundCreated { round_address } if round_address == Address::from_low_u64_be(0x456)
+        ));
+    }
+
+    #[test]
+    fn test_decode_new_project_application() {
+        let log = make_log(
+            vec![
+                NewProjectApplicationFilter::signature(),
+                H256::from_low_u64_be(0x789),
+            ],
+            vec![Token::Uint(U256::from(2)), meta_ptr_token("bafkreih")],
+        );
+
+        let event = decode_log(1, &log).unwrap().unwrap();
+        assert!(matches!(
+            event.data,
+            EventPayload::NewProjectApplication { project_id, application_index } if project_id == H256::from_low_u64_be(0x789) && application_index == U256::from(2)
+        ));
+    }
+
+    #[test]
+    fn test_decode_unsupported_event() {
+        let log = make_log(vec![H256::from_low_u64_be(1)], vec![]);
+        assert!(decode_log(1, &log).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_decode_malformed_log() {
+        let log = make_log(vec![ProjectCreatedFilter::signature()], vec![]);
+        assert!(matches!(decode_log(1, &log), Err(LogDecodingError::Abi(_))));
+
+        let mut log = make_log(
+            vec![
+                OwnerAddedFilter::signature(),
+                H256::from_low_u64_be(123),
+                H256::from(Address::from_low_u64_be(0x456)),
+            ],
+            vec![],
+        );
+        log.block_number = None;
+        assert!(matches!(
+            decode_log(1, &log),
+            Err(LogDecodingError::MissingField("blockNumber"))
+        ));
+    }
+}
@@ -1,50 +1,762 @@
 #![allow(unused_imports, dead_code)]
 
-use crate::event_handling::{event_to_changeset, DB_SCHEMA};
-use crate::event_source::event_stream_from_ndjson_stdin;
-use clap::Parser;
+use crate::chains::{load_chains, ChainConfig, ChainSource, DEFAULT_PAGE_SIZE};
+use crate::checkpoint::CheckpointStore;
+use crate::config::{
+    Config, ConfigError, InputFormat, IpfsConfig, LocalIpfsConfig, OutputFormat, SinkConfig,
+};
+use crate::contract_watchlist::ContractWatchlist;
+use crate::dialect::Dialect;
+use crate::event_handling::{discovered_contracts, event_to_changeset_with_metadata};
+use crate::event_handling::{Event, EventPayload, MetadataError};
+use crate::event_source::{
+    event_stream_from_ndjson_stdin, event_stream_from_raw_log_ndjson_stdin, ParseErrorPolicy,
+};
+use crate::export::{export, ExportFormat};
+use crate::indexer::{event_stream_from_chains, prefetch_metadata};
+use crate::ipfs::IpfsClient;
+use crate::ipfs_cache::IpfsCache;
+use crate::ipfs_local::LocalIpfs;
+use crate::listen::{apply_batches, listen};
+use crate::metadata_retry::retry_pending_metadata;
+use crate::publish::{publish_database, PublishSink};
+use crate::search::search_projects;
+use crate::server::serve;
+use crate::sink::{open_sink, Sink};
+use crate::store::Store;
+use clap::{Parser, Subcommand};
+use ethers::providers::{Http, Middleware, Provider, Ws};
+use ethers::types::Address;
+use futures::future::{self, Future};
 use futures::pin_mut;
-use futures::stream::StreamExt;
+use futures::stream::{Stream, StreamExt};
 use std::io;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_postgres::IsolationLevel;
 
+mod chains;
+mod changes;
+mod checkpoint;
+mod config;
+mod contract_watchlist;
+mod copy_sink;
+mod dialect;
+mod eth_serde;
 mod event_handling;
 mod event_source;
+mod export;
+mod graphql;
 mod indexer;
-
-// TODO allow choosing behavior on parse errors: do nothing, warn, panic.
+mod ipfs;
+mod ipfs_cache;
+mod ipfs_local;
+mod ipfs_verify;
+mod json_sink;
+mod listen;
+mod log_decoding;
+mod metadata_retry;
+mod project_metadata;
+mod publish;
+mod rest;
+mod rpc_event_source;
+mod search;
+mod server;
+mod sink;
+mod store;
+#[cfg(test)]
+mod test_helpers;
 
 #[derive(Parser, Debug)]
 struct Args {
-    /// Display warnings for parse errors
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// TOML configuration file. Defaults to gs-index.toml if it exists
+    #[arg(long, global = true, env = "GS_INDEX_CONFIG")]
+    config: Option<PathBuf>,
+    /// Display warnings for parse errors (same as --on-parse-error warn)
     #[arg(long, default_value_t = false)]
     show_warnings: bool,
-    #[arg(long, default_value_t = false)]
+    /// What to do with items that cannot be parsed
+    #[arg(long, value_enum, env = "GS_INDEX_ON_PARSE_ERROR")]
+    on_parse_error: Option<ParseErrorPolicy>,
+    #[arg(long, default_value_t = false, env = "GS_INDEX_CREATE_TABLES")]
     create_tables: bool,
+    /// Format of the NDJSON items read from stdin
+    #[arg(long, value_enum, env = "GS_INDEX_INPUT")]
+    input: Option<InputFormat>,
+    /// Chain the raw logs come from. Queried from the node when using --rpc-url or --ws-url
+    #[arg(long, env = "GS_INDEX_CHAIN_ID")]
+    chain_id: Option<u64>,
+    /// Fetch logs from a JSON-RPC node instead of reading stdin
+    #[arg(
+        long,
+        group = "node",
+        conflicts_with = "input",
+        env = "GS_INDEX_RPC_URL"
+    )]
+    rpc_url: Option<String>,
+    /// Fetch logs from a JSON-RPC node over WebSocket, then keep following new blocks
+    #[arg(
+        long,
+        group = "node",
+        conflicts_with = "input",
+        env = "GS_INDEX_WS_URL"
+    )]
+    ws_url: Option<String>,
+    /// Address of the ProjectRegistry contract
+    #[arg(long, requires = "node")]
+    registry: Option<Address>,
+    /// Address of the RoundFactory contract
+    #[arg(long, requires = "node")]
+    round_factory: Option<Address>,
+    /// Address of a round to index besides those created by the factory (can be repeated)
+    #[arg(long = "round", requires = "node")]
+    rounds: Vec<Address>,
+    #[arg(long, default_value_t = 0, requires = "node")]
+    from_block: u64,
+    /// Defaults to the latest block
+    #[arg(long, requires = "rpc_url")]
+    to_block: Option<u64>,
+    /// Number of blocks to request logs for at once
+    #[arg(long, default_value_t = DEFAULT_PAGE_SIZE, requires = "node")]
+    page_size: u64,
+    /// JSON file configuring the chains to index, instead of reading stdin
+    #[arg(long, conflicts_with_all = ["input", "node"])]
+    chains: Option<PathBuf>,
+    /// File keeping track of the last indexed event of each chain, to resume from
+    #[arg(long, env = "GS_INDEX_CHECKPOINT_FILE")]
+    checkpoint_file: Option<PathBuf>,
+    /// IPFS gateway to fetch metadata from (can be repeated)
+    #[arg(
+        long = "ipfs-gateway",
+        env = "GS_INDEX_IPFS_GATEWAYS",
+        value_delimiter = ','
+    )]
+    ipfs_gateways: Vec<String>,
+    /// Time allowed for a single IPFS request, in milliseconds
+    #[arg(long, env = "GS_INDEX_IPFS_TIMEOUT_MS")]
+    ipfs_timeout_ms: Option<u64>,
+    /// Number of times to retry all IPFS gateways before giving up
+    #[arg(long, env = "GS_INDEX_IPFS_RETRIES")]
+    ipfs_retries: Option<u32>,
+    /// Resolve IPFS documents from this directory of <cid> files, without network access
+    #[arg(long, env = "GS_INDEX_IPFS_LOCAL_DIR")]
+    ipfs_local_dir: Option<PathBuf>,
+    /// Resolve IPFS documents from this CAR archive, without network access (can be repeated)
+    #[arg(long = "ipfs-car", env = "GS_INDEX_IPFS_CARS", value_delimiter = ',')]
+    ipfs_car_files: Vec<PathBuf>,
+    /// Number of IPFS documents fetched at once, ahead of indexing
+    #[arg(long, env = "GS_INDEX_IPFS_CONCURRENCY")]
+    ipfs_concurrency: Option<usize>,
+    /// Directory where IPFS documents are cached
+    #[arg(long, global = true, env = "GS_INDEX_IPFS_CACHE_DIR")]
+    ipfs_cache_dir: Option<PathBuf>,
+    /// Number of statements to wrap in a transaction, 0 for none
+    #[arg(long, env = "GS_INDEX_BATCH_SIZE")]
+    batch_size: Option<usize>,
+    /// Load the inserts of each batch with COPY blocks, which psql reads much
+    /// faster than INSERT statements. Postgres only, needs --batch-size
+    #[arg(long, default_value_t = false, env = "GS_INDEX_COPY")]
+    copy: bool,
+    /// What to print for each event
+    #[arg(long, value_enum, env = "GS_INDEX_OUTPUT")]
+    output: Option<OutputFormat>,
+    /// Append the output to a file instead of printing it
+    #[arg(long, env = "GS_INDEX_OUTPUT_FILE")]
+    output_file: Option<PathBuf>,
+    /// Write into a SQLite database file instead of printing SQL
+    #[arg(long, conflicts_with = "output_file", env = "GS_INDEX_SQLITE_FILE")]
+    sqlite_file: Option<PathBuf>,
+    /// SQL dialect of the output. Defaults to sqlite with --sqlite-file, postgres otherwise
+    #[arg(long, value_enum, env = "GS_INDEX_DIALECT")]
+    dialect: Option<Dialect>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Fetch the metadata that could not be fetched while indexing and
+    /// store it in the database
+    RetryMetadata {
+        #[arg(long, env = "GS_INDEX_DATABASE_URL")]
+        database_url: String,
+        /// Give up on documents after this many attempts
+        #[arg(long, default_value_t = 5)]
+        max_attempts: u32,
+        /// Keep running, retrying every this many seconds
+        #[arg(long)]
+        interval_secs: Option<u64>,
+    },
+    /// Find projects by words or beginnings of words in their title or
+    /// description. Round metadata is not indexed, so rounds cannot be
+    /// searched
+    Search {
+        query: String,
+        #[arg(long, env = "GS_INDEX_DATABASE_URL")]
+        database_url: String,
+        /// Only search projects of this chain
+        #[arg(long)]
+        chain_id: Option<u64>,
+        #[arg(long, default_value_t = 20)]
+        limit: i64,
+    },
+    /// Index the events into memory and write the resulting project, round
+    /// and application tables as files, without a database
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// Directory to write the files into, created if needed
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Serve a read-only GraphQL API over the indexed tables, at /graphql,
+    /// and optionally the static JSON layout
+    Serve {
+        #[arg(long, env = "GS_INDEX_DATABASE_URL")]
+        database_url: String,
+        #[arg(long, default_value = "127.0.0.1", env = "GS_INDEX_HOST")]
+        host: String,
+        #[arg(long, default_value_t = 8080, env = "GS_INDEX_PORT")]
+        port: u16,
+        /// Also serve the per-chain JSON documents of the Grants Stack
+        /// static layout, e.g. /1/rounds.json. They lack the fields that are
+        /// not indexed, such as round metadata and amounts, so they are not
+        /// a drop-in replacement for the Grants Stack files
+        #[arg(long, default_value_t = false)]
+        rest: bool,
+    },
+    /// Index batches of events POSTed as NDJSON to /events instead of
+    /// reading stdin, answering each request once its events are applied
+    Listen {
+        #[arg(long, default_value = "127.0.0.1", env = "GS_INDEX_HOST")]
+        host: String,
+        #[arg(long, default_value_t = 8080, env = "GS_INDEX_PORT")]
+        port: u16,
+    },
+    /// Index the events into memory and keep the per-chain JSON documents
+    /// of the static layout up to date in a directory, rewriting only the
+    /// documents changed since the last write, or write them once from a
+    /// database. Same documents as `serve --rest`, not a drop-in replacement
+    /// for the Grants Stack files
+    Publish {
+        /// Directory to write the documents into, created if needed
+        #[arg(long)]
+        out: PathBuf,
+        /// Write the documents once from the tables of this database, kept
+        /// up to date by the indexer, instead of indexing the events again
+        #[arg(long, env = "GS_INDEX_DATABASE_URL")]
+        database_url: Option<String>,
+    },
+    /// Manage the IPFS cache
+    IpfsCache {
+        #[command(subcommand)]
+        command: IpfsCacheCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Validate the configuration and print the effective configuration
+    Check,
+}
+
+#[derive(Subcommand, Debug)]
+enum IpfsCacheCommand {
+    /// Fetch the metadata referenced by the configured sources into the cache
+    Warm,
+    /// Print the number and total size of cached documents
+    Stats,
+    /// Evict least recently used documents until the cache fits in the limit
+    Prune {
+        /// Size limit in bytes, defaults to ipfs.cache_max_size
+        #[arg(long)]
+        max_size: Option<u64>,
+    },
+}
+
+/// Turns the --rpc-url/--ws-url flags into the configuration of a single
+/// chain, asking the node for the chain id unless given.
+async fn cli_chain_config(args: &Args) -> ChainConfig {
+    let source = match (&args.rpc_url, &args.ws_url) {
+        (Some(url), _) => ChainSource::Rpc { url: url.clone() },
+        (_, Some(url)) => ChainSource::Ws { url: url.clone() },
+        _ => unreachable!("clap requires either --rpc-url or --ws-url"),
+    };
+    let chain_id = match (args.chain_id, &source) {
+        (Some(chain_id), _) => chain_id,
+        (None, ChainSource::Ws { url }) => Provider::<Ws>::connect(url)
+            .await
+            .expect("cannot connect to WebSocket endpoint")
+            .get_chainid()
+            .await
+            .expect("cannot get chain id")
+            .as_u64(),
+        (None, ChainSource::Rpc { url }) => Provider::<Http>::try_from(url.as_str())
+            .expect("invalid RPC URL")
+            .get_chainid()
+            .await
+            .expect("cannot get chain id")
+            .as_u64(),
+        (None, _) => unreachable!(),
+    };
+
+    ChainConfig {
+        id: chain_id,
+        name: chain_id.to_string(),
+        source,
+        registry: args.registry,
+        round_factory: args.round_factory,
+        rounds: args.rounds.clone(),
+        start_block: args.from_block,
+        end_block: args.to_block,
+        page_size: args.page_size,
+    }
 }
 
-const IPFS_GATEWAY: &str = "https://d16c97c2np8a2o.cloudfront.net/ipfs/";
+const CHECKPOINT_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Applies command-line flags and environment variables on top of the
+/// configuration file.
+async fn effective_config(args: &Args) -> Result<Config, ConfigError> {
+    let mut config = Config::load(args.config.as_deref())?;
+
+    if args.create_tables {
+        config.create_tables = true;
+    }
+    if let Some(path) = &args.checkpoint_file {
+        config.checkpoint_file = Some(path.clone());
+    }
+    if let Some(format) = args.input {
+        config.input.format = format;
+    }
+    if let Some(chain_id) = args.chain_id {
+        config.input.chain_id = Some(chain_id);
+    }
+    if let Some(path) = &args.output_file {
+        config.sink = SinkConfig::File { path: path.clone() };
+    }
+    if let Some(path) = &args.sqlite_file {
+        config.sink = SinkConfig::Sqlite { path: path.clone() };
+    }
+    if let Some(format) = args.output {
+        config.output.format = format;
+    }
+    if let Some(dialect) = args.dialect {
+        config.output.dialect = Some(dialect);
+    }
+    if !args.ipfs_gateways.is_empty() {
+        config.ipfs.gateways = args.ipfs_gateways.clone();
+    }
+    if let Some(timeout_ms) = args.ipfs_timeout_ms {
+        config.ipfs.timeout_ms = timeout_ms;
+    }
+    if let Some(retries) = args.ipfs_retries {
+        config.ipfs.retries = retries;
+    }
+    if args.ipfs_local_dir.is_some() || !args.ipfs_car_files.is_empty() {
+        config.ipfs.local = Some(LocalIpfsConfig {
+            directory: args.ipfs_local_dir.clone(),
+            car_files: args.ipfs_car_files.clone(),
+        });
+    }
+    if let Some(concurrency) = args.ipfs_concurrency {
+        config.ipfs.concurrency = concurrency;
+    }
+    if let Some(path) = &args.ipfs_cache_dir {
+        config.ipfs.cache_dir = Some(path.clone());
+    }
+    if let Some(policy) = args.on_parse_error {
+        config.errors.on_parse_error = policy;
+    } else if args.show_warnings {
+        config.errors.on_parse_error = ParseErrorPolicy::Warn;
+    }
+    if args.copy {
+        config.batching.copy = true;
+    }
+    if let Some(size) = args.batch_size {
+        config.batching.size = size;
+    }
+    if let Some(path) = &args.chains {
+        config.chains = load_chains(path).map_err(|err| ConfigError::Io(path.clone(), err))?;
+    } else if args.rpc_url.is_some() || args.ws_url.is_some() {
+        config.chains = vec![cli_chain_config(args).await];
+    }
 
-async fn ipfs_getter(cid: String) -> String {
-    let url = format!("{}{}", IPFS_GATEWAY, cid);
-    reqwest::get(url).await.unwrap().text().await.unwrap()
+    config.validate()?;
+    Ok(config)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), io::Error> {
     let args = Args::parse();
 
-    let event_stream = event_stream_from_ndjson_stdin(0, args.show_warnings);
+    let config = match effective_config(&args).await {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    match args.command {
+        Some(Command::Config {
+            command: ConfigCommand::Check,
+        }) => {
+            print!("{}", config.to_toml());
+            Ok(())
+        }
+        Some(Command::RetryMetadata {
+            database_url,
+            max_attempts,
+            interval_secs,
+        }) => {
+            retry_metadata(
+                &config,
+                &database_url,
+                max_attempts,
+                interval_secs.map(Duration::from_secs),
+            )
+            .await
+        }
+        Some(Command::Search {
+            query,
+            database_url,
+            chain_id,
+            limit,
+        }) => {
+            let client = connect(&database_url).await?;
+            let matches = search_projects(&client, &query, chain_id, limit)
+                .await
+                .map_err(io::Error::other)?;
+            for found in matches {
+                println!("{}\t{}\t{}", found.chain_id, found.project_id, found.title);
+            }
+            Ok(())
+        }
+        Some(Command::Export { format, out }) => export_tables(&config, format, &out).await,
+        Some(Command::Serve {
+            database_url,
+            host,
+            port,
+            rest,
+        }) => {
+            let client = connect(&database_url).await?;
+            let listener = std::net::TcpListener::bind((host.as_str(), port))?;
+            eprintln!("listening on http://{}", listener.local_addr()?);
+            serve(listener, Arc::new(client), rest).await
+        }
+        Some(Command::Listen { host, port }) => listen_for_events(&config, &host, port).await,
+        Some(Command::Publish {
+            out,
+            database_url: Some(database_url),
+        }) => {
+            let mut client = connect(&database_url).await?;
+            // All documents show the same state of the tables
+            let transaction = client
+                .build_transaction()
+                .isolation_level(IsolationLevel::RepeatableRead)
+                .read_only(true)
+                .start()
+                .await
+                .map_err(io::Error::other)?;
+            let written = publish_database(&transaction, &out).await?;
+            eprintln!("wrote {} documents", written);
+            Ok(())
+        }
+        Some(Command::Publish {
+            out,
+            database_url: None,
+        }) => {
+            // The documents are rendered from memory, so every run starts
+            // over from the first event.
+            if config.checkpoint_file.is_some() {
+                eprintln!("Warning: publish indexes every event again and ignores checkpoint_file; pass --database-url to publish from a database instead");
+            }
+            let config = Config {
+                checkpoint_file: None,
+                ..config
+            };
+            index(config, Box::new(PublishSink::new(&out))).await
+        }
+        Some(Command::IpfsCache { command }) => {
+            let Some(cache) = open_ipfs_cache(&config.ipfs)? else {
+                eprintln!("ipfs.cache_dir or --ipfs-cache-dir is required");
+                process::exit(1);
+            };
+            match command {
+                IpfsCacheCommand::Warm => warm_ipfs_cache(&config, cache).await,
+                IpfsCacheCommand::Stats => {
+                    let stats = cache.stats()?;
+                    println!("{} documents, {} bytes", stats.entries, stats.bytes);
+                    Ok(())
+                }
+                IpfsCacheCommand::Prune { max_size } => {
+                    let Some(max_size) = max_size.or(config.ipfs.cache_max_size) else {
+                        eprintln!("--max-size or ipfs.cache_max_size is required");
+                        process::exit(1);
+                    };
+                    let removed = cache.prune(max_size)?;
+                    println!(
+                        "removed {} documents, {} bytes",
+                        removed.entries, removed.bytes
+                    );
+                    Ok(())
+                }
+            }
+        }
+        None => {
+            let sink = open_sink(&config)?;
+            index(config, sink).await
+        }
+    }
+}
+
+fn open_ipfs_cache(config: &IpfsConfig) -> io::Result<Option<IpfsCache>> {
+    config
+        .cache_dir
+        .as_ref()
+        .map(|path| IpfsCache::open(path, config.cache_max_size))
+        .transpose()
+}
+
+fn ipfs_client(config: &IpfsConfig) -> io::Result<IpfsClient> {
+    let client = IpfsClient::new(config);
+    Ok(match open_ipfs_cache(config)? {
+        Some(cache) => client.with_cache(cache),
+        None => client,
+    })
+}
+
+type IpfsGetter = Arc<
+    dyn Fn(String) -> Pin<Box<dyn Future<Output = Result<String, MetadataError>> + Send>>
+        + Send
+        + Sync,
+>;
+
+/// Resolves documents from local files when `ipfs.local` is configured,
+/// otherwise through the gateways.
+fn ipfs_getter(config: &IpfsConfig) -> io::Result<IpfsGetter> {
+    if let Some(local) = &config.local {
+        let local = LocalIpfs::open(local.directory.as_deref(), &local.car_files)?;
+        return Ok(Arc::new(move |cid: String| {
+            let content = local.get(&cid);
+            Box::pin(async move { content.map_err(MetadataError::from) })
+        }));
+    }
+    let ipfs = ipfs_client(config)?;
+    Ok(Arc::new(move |cid: String| {
+        let ipfs = ipfs.clone();
+        Box::pin(async move { ipfs.get(&cid).await.map_err(MetadataError::from) })
+    }))
+}
+
+async fn connect(database_url: &str) -> io::Result<tokio_postgres::Client> {
+    let (client, connection) = tokio_postgres::connect(database_url, tokio_postgres::NoTls)
+        .await
+        .map_err(io::Error::other)?;
+    tokio::spawn(connection);
+    Ok(client)
+}
+
+/// Retries the documents that could not be fetched while indexing, once or
+/// every `interval`.
+async fn retry_metadata(
+    config: &Config,
+    database_url: &str,
+    max_attempts: u32,
+    interval: Option<Duration>,
+) -> io::Result<()> {
+    let mut client = connect(database_url).await?;
+    let ipfs_getter = ipfs_getter(&config.ipfs)?;
+
+    loop {
+        let stats = retry_pending_metadata(
+            &mut client,
+            |cid| ipfs_getter(cid),
+            max_attempts,
+            config.ipfs.concurrency,
+        )
+        .await
+        .map_err(io::Error::other)?;
+        eprintln!(
+            "{} documents resolved, {} still failing, {} superseded",
+            stats.resolved, stats.failed, stats.superseded
+        );
+        match interval {
+            Some(interval) => tokio::time::sleep(interval).await,
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Events of the configured chains, or of stdin when there are none.
+async fn event_stream(
+    config: &Config,
+    checkpoints: &CheckpointStore,
+    watchlist: ContractWatchlist,
+) -> io::Result<Pin<Box<dyn Stream<Item = (Event, usize)>>>> {
+    let on_parse_error = config.errors.on_parse_error;
+    Ok(if !config.chains.is_empty() {
+        Box::pin(
+            event_stream_from_chains(&config.chains, checkpoints, watchlist, on_parse_error)
+                .await?,
+        )
+    } else {
+        match config.input.format {
+            InputFormat::Events => Box::pin(event_stream_from_ndjson_stdin(0, on_parse_error)),
+            InputFormat::RawLogs => Box::pin(event_stream_from_raw_log_ndjson_stdin(
+                config
+                    .input
+                    .chain_id
+                    .expect("validated with the configuration"),
+                0,
+                on_parse_error,
+            )),
+        }
+    })
+}
+
+/// Fetches every metadata document the sources refer to, without indexing,
+/// so that later runs can be served entirely from the cache.
+async fn warm_ipfs_cache(config: &Config, cache: IpfsCache) -> io::Result<()> {
+    let ipfs = IpfsClient::new(&config.ipfs).with_cache(cache);
+    let (mut fetched, mut failed) = (0, 0);
+    event_stream(
+        config,
+        &CheckpointStore::default(),
+        ContractWatchlist::new(),
+    )
+    .await?
+    .filter_map(|(event, _index)| {
+        future::ready(match event.data {
+            EventPayload::MetadataUpdated { meta_ptr, .. } => Some(meta_ptr.pointer),
+            _ => None,
+        })
+    })
+    .map(|cid| {
+        let ipfs = ipfs.clone();
+        async move { ipfs.get(&cid).await }
+    })
+    .buffer_unordered(config.ipfs.concurrency)
+    .for_each(|result| {
+        match result {
+            Ok(_) => fetched += 1,
+            Err(err) => {
+                eprintln!("{}", err);
+                failed += 1;
+            }
+        }
+        future::ready(())
+    })
+    .await;
+    println!("{} documents cached, {} failed", fetched, failed);
+    Ok(())
+}
+
+/// Replays all the events into an in-memory store, ignoring checkpoints,
+/// and exports its tables into `directory`.
+async fn export_tables(config: &Config, format: ExportFormat, directory: &Path) -> io::Result<()> {
+    let ipfs_getter = ipfs_getter(&config.ipfs)?;
+    let watchlist = ContractWatchlist::new();
+    let event_stream = event_stream(config, &CheckpointStore::default(), watchlist.clone())
+        .await?
+        .inspect(|(event, _index)| watchlist.register(event, &discovered_contracts(event)));
+    let event_stream = prefetch_metadata(
+        event_stream,
+        |cid: String| ipfs_getter(cid),
+        config.ipfs.concurrency,
+    );
     pin_mut!(event_stream);
 
-    if args.create_tables {
-        println!("{}", DB_SCHEMA);
+    let mut store = Store::new();
+    while let Some((event, _index, metadata)) = event_stream.next().await {
+        let change_set = event_to_changeset_with_metadata(&event, metadata);
+        store
+            .apply_all(&change_set.changes)
+            .map_err(io::Error::other)?;
     }
+    for path in export(&store, format, directory)? {
+        eprintln!("wrote {}", path.display());
+    }
+    Ok(())
+}
+
+/// Indexes the batches of events pushed over HTTP into the configured sink.
+async fn listen_for_events(config: &Config, host: &str, port: u16) -> io::Result<()> {
+    let ipfs_getter = ipfs_getter(&config.ipfs)?;
+    let mut sink = open_sink(config)?;
+    if config.create_tables {
+        sink.write_schema()?;
+    }
+    sink.flush()?;
+
+    let listener = std::net::TcpListener::bind((host, port))?;
+    eprintln!("listening on http://{}/events", listener.local_addr()?);
+    // Batches are applied one at a time, in the order they are received
+    let (batches, received) = tokio::sync::mpsc::channel(1);
+    let server = tokio::spawn(listen(listener, batches));
+    apply_batches(
+        received,
+        sink.as_mut(),
+        |cid: String| ipfs_getter(cid),
+        config.ipfs.concurrency,
+    )
+    .await?;
+    server.abort();
+    Ok(())
+}
+
+async fn index(config: Config, mut sink: Box<dyn Sink>) -> Result<(), io::Error> {
+    let mut checkpoints = match &config.checkpoint_file {
+        Some(path) => CheckpointStore::load(path)?,
+        None => CheckpointStore::default(),
+    };
+    let watchlist = ContractWatchlist::with_contracts(checkpoints.contracts().to_vec());
+
+    let ipfs_getter = ipfs_getter(&config.ipfs)?;
+    // Contracts are registered as soon as their event comes out of the
+    // source rather than once it is indexed, so that looking ahead for
+    // metadata does not delay their backfill.
+    let event_stream = event_stream(&config, &checkpoints, watchlist.clone())
+        .await?
+        .inspect(|(event, _index)| watchlist.register(event, &discovered_contracts(event)));
+    let event_stream = prefetch_metadata(
+        event_stream,
+        |cid: String| ipfs_getter(cid),
+        config.ipfs.concurrency,
+    );
+    pin_mut!(event_stream);
+
+    if config.create_tables {
+        sink.write_schema()?;
+    }
+
+    let mut last_checkpoint_save = Instant::now();
+    while let Some((event, _index, metadata)) = event_stream.next().await {
+        let change_set = event_to_changeset_with_metadata(&event, metadata);
+        sink.write_changes(&event, &change_set.changes)?;
 
-    while let Some((event, _index)) = event_stream.next().await {
-        let change_set = event_to_changeset(&event, |cid: String| Box::pin(ipfs_getter(cid))).await;
-        println!("{};", change_set.sql);
+        checkpoints.update(&event);
+        // Only checkpoint between transactions, so that resuming never skips
+        // the events of a batch that was not committed.
+        if last_checkpoint_save.elapsed() >= CHECKPOINT_SAVE_INTERVAL && !sink.is_in_batch() {
+            sink.flush()?;
+            checkpoints.set_contracts(watchlist.contracts());
+            checkpoints.save()?;
+            last_checkpoint_save = Instant::now();
+        }
     }
+    sink.finish()?;
+    checkpoints.set_contracts(watchlist.contracts());
+    checkpoints.save()?;
 
     Ok(())
 }
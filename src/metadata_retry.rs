@@ -0,0 +1,218 @@
+use ethers::types::U256;
+use futures::stream::{self, StreamExt};
+use futures::Future;
+use tokio_postgres::{Error, GenericClient};
+
+use crate::event_handling::{project_metadata_sql, MetadataError};
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RetryStats {
+    /// Fetched and stored, or found not to be valid metadata
+    pub resolved: usize,
+    /// Still not available, left in the queue
+    pub failed: usize,
+    /// Replaced by a newer document while being fetched
+    pub superseded: usize,
+}
+
+struct PendingDocument {
+    chain_id: i64,
+    project_id: String,
+    cid: String,
+}
+
+/// Fetches the documents queued in `pending_metadata` that were attempted
+/// fewer than `max_attempts` times, and stores those that can be fetched now.
+/// Each document is applied in its own transaction, and only if it is still
+/// the one pending for its project, so the job can run alongside indexing.
+pub async fn retry_pending_metadata<F, Fut>(
+    client: &mut impl GenericClient,
+    fetch: F,
+    max_attempts: u32,
+    concurrency: usize,
+) -> Result<RetryStats, Error>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<String, MetadataError>>,
+{
+    let pending: Vec<PendingDocument> = client
+        .query(
+            "SELECT chain_id, project_id::TEXT, cid FROM pending_metadata WHERE attempts < $1 ORDER BY chain_id, project_id",
+            &[&(max_attempts as i32)],
+        )
+        .await?
+        .into_iter()
+        .map(|row| PendingDocument {
+            chain_id: row.get(0),
+            project_id: row.get(1),
+            cid: row.get(2),
+        })
+        .collect();
+
+    let mut results = stream::iter(pending)
+        .map(|document| {
+            let fetching = fetch(document.cid.clone());
+            async move { (document, fetching.await) }
+        })
+        .buffer_unordered(concurrency);
+
+    let mut stats = RetryStats::default();
+    while let Some((document, metadata)) = results.next().await {
+        let transaction = client.transaction().await?;
+        let key: [&(dyn tokio_postgres::types::ToSql + Sync); 3] =
+            [&document.chain_id, &document.project_id, &document.cid];
+        match metadata {
+            Err(err @ MetadataError::Fetch(_)) => {
+                let error = err.to_string();
+                let updated = transaction
+                    .execute(
+                        "UPDATE pending_metadata SET attempts = attempts + 1, last_error = $4 WHERE chain_id = $1 AND project_id = $2::TEXT::NUMERIC AND cid = $3",
+                        &[key[0], key[1], key[2], &error],
+                    )
+                    .await?;
+                if updated == 0 {
+                    stats.superseded += 1;
+                    continue;
+                }
+                transaction
+                    .execute(
+                        "UPDATE project SET metadata_error = $3 WHERE chain_id = $1 AND project_id = $2::TEXT::NUMERIC",
+                        &[key[0], key[1], &error],
+                    )
+                    .await?;
+                stats.failed += 1;
+            }
+            // Fetched, or known to be unusable however often it is fetched
+            metadata => {
+                let claimed = transaction
+                    .execute(
+                        "DELETE FROM pending_metadata WHERE chain_id = $1 AND project_id = $2::TEXT::NUMERIC AND cid = $3",
+                        &key,
+                    )
+                    .await?;
+                if claimed == 0 {
+                    stats.superseded += 1;
+                    continue;
+                }
+                let project_id = U256::from_dec_str(&document.project_id)
+                    .expect("project ids are stored as decimal numbers");
+                transaction
+                    .batch_execute(&project_metadata_sql(
+                        document.chain_id as u64,
+                        &project_id,
+                        &document.cid,
+                        metadata,
+                    ))
+                    .await?;
+                stats.resolved += 1;
+            }
+        }
+        transaction.commit().await?;
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{connect, transaction_with_schema};
+
+    async fn fetch(cid: String) -> Result<String, MetadataError> {
+        match cid.as_str() {
+            "available" => Ok(r#"{"title":"x","description":"y"}"#.to_string()),
+            "garbage" => Ok("<html>".to_string()),
+            "corrupt" => Err(MetadataError::InvalidContent(
+                "content does not match corrupt".to_string(),
+            )),
+            _ => Err(MetadataError::Fetch("HTTP 404 Not Found".to_string())),
+        }
+    }
+
+    #[ignore = "needs Postgres, see GS_INDEX_TEST_DATABASE"]
+    #[tokio::test]
+    async fn test_retry_pending_metadata() {
+        let mut client = connect().await;
+        let mut transaction = transaction_with_schema(&mut client).await;
+
+        for (project_id, cid) in [
+            (1, "available"),
+            (2, "garbage"),
+            (3, "missing"),
+            (4, "corrupt"),
+        ] {
+            transaction
+                .batch_execute(&format!(
+                    "INSERT INTO project (chain_id, project_id, created_at_block) VALUES (1, {}, 1); {}",
+                    project_id,
+                    project_metadata_sql(
+                        1,
+                        &U256::from(project_id),
+                        cid,
+                        Err(MetadataError::Fetch("timed out".to_string()))
+                    )
+                ))
+                .await
+                .unwrap();
+        }
+
+        let stats = retry_pending_metadata(&mut transaction, fetch, 5, 2)
+            .await
+            .unwrap();
+        assert_eq!(
+            stats,
+            RetryStats {
+                resolved: 3,
+                failed: 1,
+                superseded: 0
+            }
+        );
+
+        let rows = transaction
+            .query(
+                "SELECT project_id::TEXT, metadata_status, metadata_error FROM project ORDER BY project_id",
+                &[],
+            )
+            .await
+            .unwrap();
+        let projects: Vec<(String, String, Option<String>)> = rows
+            .iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect();
+        assert_eq!(
+            projects,
+            vec![
+                ("1".to_string(), "ok".to_string(), None),
+                (
+                    "2".to_string(),
+                    "invalid".to_string(),
+                    Some(
+                        "metadata is not valid JSON: expected value at line 1 column 1".to_string()
+                    )
+                ),
+                (
+                    "3".to_string(),
+                    "pending".to_string(),
+                    Some("cannot fetch metadata: HTTP 404 Not Found".to_string())
+                ),
+                (
+                    "4".to_string(),
+                    "invalid".to_string(),
+                    Some("invalid metadata content: content does not match corrupt".to_string())
+                ),
+            ]
+        );
+
+        let row = transaction
+            .query_one("SELECT cid, attempts FROM pending_metadata", &[])
+            .await
+            .unwrap();
+        assert_eq!(row.get::<_, String>(0), "missing");
+        assert_eq!(row.get::<_, i32>(1), 2);
+
+        // Given up after two attempts
+        let stats = retry_pending_metadata(&mut transaction, fetch, 2, 2)
+            .await
+            .unwrap();
+        assert_eq!(stats, RetryStats::default());
+    }
+}
@@ -0,0 +1,125 @@
+//! Grants Stack project metadata, as published to IPFS by the project
+//! registry UI.
+
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectMetadata {
+    pub title: String,
+    pub description: String,
+    #[serde(default, deserialize_with = "non_empty")]
+    pub website: Option<String>,
+    /// CID of the logo image
+    #[serde(default, deserialize_with = "non_empty")]
+    pub logo_img: Option<String>,
+    /// CID of the banner image
+    #[serde(default, deserialize_with = "non_empty")]
+    pub banner_img: Option<String>,
+    #[serde(default, deserialize_with = "non_empty")]
+    pub project_twitter: Option<String>,
+    /// GitHub account of the person who created the project
+    #[serde(default, deserialize_with = "non_empty")]
+    pub user_github: Option<String>,
+    /// GitHub organization of the project
+    #[serde(default, deserialize_with = "non_empty")]
+    pub project_github: Option<String>,
+    /// Verifiable credentials of the accounts above, by provider (e.g.
+    /// "twitter", "github")
+    #[serde(default)]
+    pub credentials: BTreeMap<String, Credential>,
+}
+
+/// A Gitcoin Passport verifiable credential. Only the fields needed to tell
+/// who issued it and what it attests are typed.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Credential {
+    pub issuer: String,
+    pub issuance_date: String,
+    pub credential_subject: CredentialSubject,
+    #[serde(flatten)]
+    pub other: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CredentialSubject {
+    /// DID of the project owner, e.g. `did:pkh:eip155:1:0x…`
+    pub id: String,
+    /// What was verified, e.g. `ClearTextTwitter#handle`
+    pub provider: String,
+    #[serde(flatten)]
+    pub other: BTreeMap<String, serde_json::Value>,
+}
+
+/// The UI stores fields left blank as empty strings.
+fn non_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let value = Option::<String>::deserialize(deserializer)?;
+    Ok(value.filter(|value| !value.is_empty()))
+}
+
+impl ProjectMetadata {
+    /// Parses a metadata document, reporting why it does not match the
+    /// schema if it does not.
+    pub fn parse(document: &str) -> Result<Self, String> {
+        serde_json::from_str(document).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let metadata = ProjectMetadata::parse(
+            r#"{
+                "title": "Gitcoin",
+                "description": "Funding public goods",
+                "website": "https://gitcoin.co",
+                "logoImg": "bafkreifjjcie6lypi6ny7amxnfftagclbuxndqonfipmb64f2km2devei4",
+                "bannerImg": "",
+                "projectTwitter": "gitcoin",
+                "userGithub": "",
+                "projectGithub": "gitcoinco",
+                "credentials": {
+                    "twitter": {
+                        "type": ["VerifiableCredential"],
+                        "issuer": "did:key:z6MkghvGHLobLEdj1bgRLhS4LPGJAvbMA1tn2zcRyqmYU5LC",
+                        "issuanceDate": "2023-01-01T00:00:00.000Z",
+                        "credentialSubject": {
+                            "id": "did:pkh:eip155:1:0x0000000000000000000000000000000000000123",
+                            "provider": "ClearTextTwitter#gitcoin"
+                        },
+                        "proof": {"type": "Ed25519Signature2018"}
+                    }
+                },
+                "createdAt": 1672531200000
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(metadata.title, "Gitcoin");
+        assert_eq!(metadata.banner_img, None);
+        assert_eq!(metadata.user_github, None);
+        assert_eq!(metadata.project_github.as_deref(), Some("gitcoinco"));
+        assert_eq!(
+            metadata.credentials["twitter"].credential_subject.provider,
+            "ClearTextTwitter#gitcoin"
+        );
+        assert!(metadata.credentials["twitter"].other.contains_key("proof"));
+    }
+
+    #[test]
+    fn test_parse_reports_schema_errors() {
+        assert_eq!(
+            ProjectMetadata::parse(r#"{"description": "x"}"#),
+            Err("missing field `title` at line 1 column 20".to_string())
+        );
+        assert_eq!(
+            ProjectMetadata::parse(r#"{"title": "x", "description": "y", "website": 1}"#),
+            Err("invalid type: integer `1`, expected a string at line 1 column 47".to_string())
+        );
+    }
+}
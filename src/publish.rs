@@ -0,0 +1,277 @@
+//! Writes the static JSON layout as files, for hosting on object storage
+//! without running a server.
+
+use ethers::types::Address;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio_postgres::GenericClient;
+
+use crate::changes::{Change, Row, Value};
+use crate::event_handling::Event;
+use crate::rest::Document;
+use crate::sink::Sink;
+use crate::store::Store;
+
+/// Indexes into an in-memory store and, on every flush, rewrites the
+/// documents touched by the changes written since the previous one.
+pub struct PublishSink {
+    directory: PathBuf,
+    store: Store,
+    touched: BTreeSet<Document>,
+}
+
+impl PublishSink {
+    pub fn new(directory: &Path) -> Self {
+        PublishSink {
+            directory: directory.to_path_buf(),
+            store: Store::new(),
+            touched: BTreeSet::new(),
+        }
+    }
+
+    fn write_documents(&mut self) -> io::Result<()> {
+        for document in std::mem::take(&mut self.touched) {
+            if let Some(content) = document.render_store(&self.store) {
+                write_document(&self.directory, &document, &content)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the document to a temporary file renamed over the previous
+/// version, so that a sync to object storage never picks up half a file.
+fn write_document(
+    directory: &Path,
+    document: &Document,
+    content: &serde_json::Value,
+) -> io::Result<()> {
+    let path = directory.join(document.path());
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let partial = path.with_extension("json.partial");
+    fs::write(&partial, content.to_string())?;
+    fs::rename(&partial, &path)
+}
+
+/// Writes every document from the tables of a database the indexer writes
+/// to, rather than indexing the events again. Returns the number of
+/// documents written.
+pub async fn publish_database(client: &impl GenericClient, directory: &Path) -> io::Result<usize> {
+    let mut documents = vec![];
+    let chains = client
+        .query(
+            "SELECT chain_id FROM project UNION SELECT chain_id FROM round ORDER BY chain_id",
+            &[],
+        )
+        .await
+        .map_err(io::Error::other)?;
+    for row in chains {
+        let chain_id = row.get::<_, i64>(0) as u64;
+        documents.push(Document::Rounds { chain_id });
+        documents.push(Document::Projects { chain_id });
+    }
+    let rounds = client
+        .query(
+            "SELECT chain_id, round_address FROM round ORDER BY chain_id, round_address",
+            &[],
+        )
+        .await
+        .map_err(io::Error::other)?;
+    // Rounds with an invalid address are reported when rendering rounds.json
+    documents.extend(rounds.iter().filter_map(|row| {
+        Some(Document::Applications {
+            chain_id: row.get::<_, i64>(0) as u64,
+            round: row.get::<_, &str>(1).parse().ok()?,
+        })
+    }));
+
+    let mut written = 0;
+    for document in documents {
+        if let Some(content) = document.render(client).await.map_err(io::Error::other)? {
+            write_document(directory, &document, &content)?;
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+fn column<'a>(row: &'a Row, name: &str) -> Option<&'a Value> {
+    row.iter()
+        .find(|(column, _)| column.to_string() == name)
+        .map(|(_, value)| value)
+}
+
+/// The documents showing rows changed by `change`. A new round gets its
+/// empty list of applications.
+fn touched_documents(change: &Change) -> Vec<Document> {
+    let (table, row) = match change {
+        Change::Insert { table, values } => (table, values),
+        Change::Update { table, key, .. }
+        | Change::Upsert { table, key, .. }
+        | Change::Delete { table, key }
+        | Change::AddToList { table, key, .. }
+        | Change::RemoveFromList { table, key, .. } => (table, key),
+    };
+    let Some(Value::Integer(chain_id)) = column(row, "chain_id") else {
+        return vec![];
+    };
+    let chain_id = *chain_id;
+    let round = match column(row, "round_address") {
+        Some(Value::Text(address)) => address.parse::<Address>().ok(),
+        _ => None,
+    };
+    match (table.to_string().as_str(), round) {
+        ("project", _) => vec![Document::Projects { chain_id }],
+        ("round", Some(round)) => vec![
+            Document::Rounds { chain_id },
+            Document::Applications { chain_id, round },
+        ],
+        ("application", Some(round)) => vec![Document::Applications { chain_id, round }],
+        _ => vec![],
+    }
+}
+
+impl Sink for PublishSink {
+    fn write_schema(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_changes(&mut self, event: &Event, changes: &[Change]) -> io::Result<()> {
+        self.store.write_changes(event, changes)?;
+        self.touched
+            .extend(changes.iter().flat_map(touched_documents));
+        Ok(())
+    }
+
+    fn write_atomically(&mut self, change_sets: &[(Event, Vec<Change>)]) -> io::Result<()> {
+        self.store.write_atomically(change_sets)?;
+        self.touched.extend(
+            change_sets
+                .iter()
+                .flat_map(|(_event, changes)| changes.iter().flat_map(touched_documents)),
+        );
+        Ok(())
+    }
+
+    fn is_in_batch(&self) -> bool {
+        false
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_documents()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_documents()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handling::{event_to_changeset_with_metadata, EventPayload};
+    use crate::test_helpers::{client_with_schema, event};
+    use ethers::types::{H256, U256};
+    use serde_json::json;
+
+    fn write(sink: &mut PublishSink, event: Event) {
+        let change_set = event_to_changeset_with_metadata(&event, None);
+        sink.write_changes(&event, &change_set.changes).unwrap();
+    }
+
+    fn read(directory: &Path, path: &str) -> serde_json::Value {
+        serde_json::from_str(&fs::read_to_string(directory.join(path)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_publish() {
+        let directory = tempfile::TempDir::new().unwrap();
+        let mut sink = PublishSink::new(directory.path());
+        let round = Address::from_low_u64_be(0xa);
+        let applications = "1/rounds/0x000000000000000000000000000000000000000A/applications.json";
+
+        write(
+            &mut sink,
+            event(
+                1,
+                EventPayload::ProjectCreated {
+                    project_id: U256::one(),
+                },
+            ),
+        );
+        write(
+            &mut sink,
+            event(
+                2,
+                EventPayload::RoundCreated {
+                    round_address: round,
+                },
+            ),
+        );
+        sink.flush().unwrap();
+        assert_eq!(read(directory.path(), "1/projects.json")[0]["id"], "1");
+        assert_eq!(
+            read(directory.path(), "1/rounds.json"),
+            json!([{ "id": "0x000000000000000000000000000000000000000A", "createdAtBlock": 2 }])
+        );
+        assert_eq!(read(directory.path(), applications), json!([]));
+
+        // Only the applications of the round are rewritten
+        fs::remove_file(directory.path().join("1/projects.json")).unwrap();
+        write(
+            &mut sink,
+            Event {
+                address: round,
+                ..event(
+                    3,
+                    EventPayload::NewProjectApplication {
+                        project_id: H256::from_low_u64_be(1),
+                        application_index: U256::zero(),
+                    },
+                )
+            },
+        );
+        sink.finish().unwrap();
+        assert_eq!(read(directory.path(), applications)[0]["status"], "PENDING");
+        assert!(!directory.path().join("1/projects.json").exists());
+        assert!(!directory
+            .path()
+            .join(format!("{}.partial", applications))
+            .exists());
+    }
+
+    #[ignore = "needs Postgres, see GS_INDEX_TEST_DATABASE"]
+    #[tokio::test]
+    async fn test_publish_database() {
+        let client = client_with_schema().await;
+        client
+            .batch_execute(
+                "INSERT INTO project (chain_id, project_id, created_at_block) VALUES (1, 1, 1);
+                INSERT INTO round VALUES (1, '0x000000000000000000000000000000000000000a', 2);",
+            )
+            .await
+            .unwrap();
+
+        let directory = tempfile::TempDir::new().unwrap();
+        assert_eq!(
+            publish_database(&client, directory.path()).await.unwrap(),
+            3
+        );
+        assert_eq!(read(directory.path(), "1/projects.json")[0]["id"], "1");
+        assert_eq!(
+            read(directory.path(), "1/rounds.json"),
+            json!([{ "id": "0x000000000000000000000000000000000000000A", "createdAtBlock": 2 }])
+        );
+        assert_eq!(
+            read(
+                directory.path(),
+                "1/rounds/0x000000000000000000000000000000000000000A/applications.json"
+            ),
+            json!([])
+        );
+    }
+}
@@ -0,0 +1,506 @@
+//! Per-chain JSON documents in the layout of the Grants Stack static JSON
+//! files, e.g. `/1/rounds.json`, built from the indexed tables. They only
+//! hold what is indexed, so they are not a drop-in replacement for the
+//! Grants Stack files: see the README for the missing fields.
+
+use ethers::types::Address;
+use ethers::utils::to_checksum;
+use serde::Serialize;
+use tokio_postgres::{Error, GenericClient};
+
+use crate::changes::Value;
+use crate::eth_serde::checksummed_address;
+use crate::store::{Record, Store};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Document {
+    /// `{chainId}/rounds.json`
+    Rounds { chain_id: u64 },
+    /// `{chainId}/projects.json`
+    Projects { chain_id: u64 },
+    /// `{chainId}/rounds/{round}/applications.json`
+    Applications { chain_id: u64, round: Address },
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct Round {
+    #[serde(with = "checksummed_address")]
+    id: Address,
+    created_at_block: i64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct Project {
+    /// The project number in the registry, uint256 in decimal. Applications
+    /// refer to projects by a bytes32 id derived from the chain, registry
+    /// address and number, which is not indexed.
+    id: String,
+    metadata: Option<serde_json::Value>,
+    owners: Vec<String>,
+    created_at_block: i64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct Application {
+    /// The application index, in decimal
+    id: String,
+    project_id: String,
+    #[serde(with = "checksummed_address")]
+    round_id: Address,
+    status: String,
+    votes: i32,
+    unique_contributors: i32,
+    metadata: Option<serde_json::Value>,
+    created_at_block: i64,
+    status_updated_at_block: i64,
+    status_snapshots: Option<serde_json::Value>,
+}
+
+fn json(text: Option<String>) -> Option<serde_json::Value> {
+    text.and_then(|text| serde_json::from_str(&text).ok())
+}
+
+fn to_json(value: impl Serialize) -> serde_json::Value {
+    serde_json::to_value(value).expect("documents can be serialized")
+}
+
+fn record_integer(record: &Record, column: &str) -> i64 {
+    match record.get(column) {
+        Some(Value::Integer(value)) => *value as i64,
+        _ => 0,
+    }
+}
+
+fn record_text(record: &Record, column: &str) -> String {
+    match record.get(column) {
+        Some(Value::Text(value)) => value.clone(),
+        Some(Value::Numeric(value)) => value.to_string(),
+        _ => String::new(),
+    }
+}
+
+fn record_json(record: &Record, column: &str) -> Option<serde_json::Value> {
+    match record.get(column) {
+        Some(Value::Json(text)) => json(Some(text.clone())),
+        _ => None,
+    }
+}
+
+/// Addresses written by the indexer are always valid. A row with any other
+/// address is left out of the documents rather than published with a made
+/// up one.
+fn address(text: &str) -> Option<Address> {
+    match checksummed_address::parse(text) {
+        Ok(address) => Some(address),
+        Err(err) => {
+            eprintln!("Warning: skipping round: {}", err);
+            None
+        }
+    }
+}
+
+impl Document {
+    /// The document at `path`, relative to the root of the layout, if any.
+    /// Round addresses are accepted in any case.
+    pub fn parse(path: &str) -> Option<Document> {
+        let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        let chain_id = segments.first()?.parse().ok()?;
+        match segments[1..] {
+            ["rounds.json"] => Some(Document::Rounds { chain_id }),
+            ["projects.json"] => Some(Document::Projects { chain_id }),
+            ["rounds", round, "applications.json"] => Some(Document::Applications {
+                chain_id,
+                round: checksummed_address::parse(&round.to_lowercase()).ok()?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Path of the document relative to the root of the layout, with round
+    /// addresses checksummed.
+    pub fn path(&self) -> String {
+        match self {
+            Document::Rounds { chain_id } => format!("{}/rounds.json", chain_id),
+            Document::Projects { chain_id } => format!("{}/projects.json", chain_id),
+            Document::Applications { chain_id, round } => format!(
+                "{}/rounds/{}/applications.json",
+                chain_id,
+                to_checksum(round, None)
+            ),
+        }
+    }
+
+    /// The content of the document, or `None` for the applications of a
+    /// round that is not indexed.
+    pub async fn render(
+        &self,
+        client: &impl GenericClient,
+    ) -> Result<Option<serde_json::Value>, Error> {
+        Ok(match *self {
+            Document::Rounds { chain_id } => {
+                let rows = client
+                    .query(
+                        "SELECT round_address, created_at_block FROM round WHERE chain_id = $1 ORDER BY created_at_block, round_address",
+                        &[&(chain_id as i64)],
+                    )
+                    .await?;
+                let rounds: Vec<Round> = rows
+                    .iter()
+                    .filter_map(|row| {
+                        Some(Round {
+                            id: address(row.get(0))?,
+                            created_at_block: row.get(1),
+                        })
+                    })
+                    .collect();
+                Some(to_json(rounds))
+            }
+            Document::Projects { chain_id } => {
+                let rows = client
+                    .query(
+                        "SELECT project_id::TEXT, metadata::TEXT, owners::TEXT, created_at_block FROM project WHERE chain_id = $1 ORDER BY project.project_id",
+                        &[&(chain_id as i64)],
+                    )
+                    .await?;
+                let projects: Vec<Project> = rows
+                    .iter()
+                    .map(|row| Project {
+                        id: row.get(0),
+                        metadata: json(row.get(1)),
+                        owners: serde_json::from_str(row.get(2)).unwrap_or_default(),
+                        created_at_block: row.get(3),
+                    })
+                    .collect();
+                Some(to_json(projects))
+            }
+            Document::Applications { chain_id, round } => {
+                let round_address = format!("{:#x}", round);
+                let is_indexed = client
+                    .query_opt(
+                        "SELECT 1 FROM round WHERE chain_id = $1 AND round_address = $2 LIMIT 1",
+                        &[&(chain_id as i64), &round_address],
+                    )
+                    .await?
+                    .is_some();
+                if !is_indexed {
+                    return Ok(None);
+                }
+                let rows = client
+                    .query(
+                        "SELECT \"index\"::TEXT, project_id, status, vote_count, unique_contributor_count, metadata::TEXT, created_at_block, status_updated_at_block, status_snapshots::TEXT FROM application WHERE chain_id = $1 AND round_address = $2 ORDER BY application.\"index\"",
+                        &[&(chain_id as i64), &round_address],
+                    )
+                    .await?;
+                let applications: Vec<Application> = rows
+                    .iter()
+                    .map(|row| Application {
+                        id: row.get(0),
+                        project_id: row.get(1),
+                        round_id: round,
+                        status: row.get(2),
+                        votes: row.get(3),
+                        unique_contributors: row.get(4),
+                        metadata: json(row.get(5)),
+                        created_at_block: row.get(6),
+                        status_updated_at_block: row.get(7),
+                        status_snapshots: json(row.get(8)),
+                    })
+                    .collect();
+                Some(to_json(applications))
+            }
+        })
+    }
+
+    /// Same as `render`, from the tables of an in-memory store.
+    pub fn render_store(&self, store: &Store) -> Option<serde_json::Value> {
+        Some(match *self {
+            Document::Rounds { chain_id } => {
+                let chain = [Value::Integer(chain_id)];
+                let mut rounds: Vec<Round> = store
+                    .rows_with_prefix("round", &chain)
+                    .filter_map(|record| {
+                        Some(Round {
+                            id: address(&record_text(record, "round_address"))?,
+                            created_at_block: record_integer(record, "created_at_block"),
+                        })
+                    })
+                    .collect();
+                rounds.sort_by_key(|round| (round.created_at_block, round.id));
+                to_json(rounds)
+            }
+            Document::Projects { chain_id } => {
+                let chain = [Value::Integer(chain_id)];
+                let projects: Vec<Project> = store
+                    .rows_with_prefix("project", &chain)
+                    .map(|record| Project {
+                        id: record_text(record, "project_id"),
+                        metadata: record_json(record, "metadata"),
+                        owners: record_json(record, "owners")
+                            .and_then(|owners| serde_json::from_value(owners).ok())
+                            .unwrap_or_default(),
+                        created_at_block: record_integer(record, "created_at_block"),
+                    })
+                    .collect();
+                to_json(projects)
+            }
+            Document::Applications { chain_id, round } => {
+                let key = [
+                    Value::Integer(chain_id),
+                    Value::Text(format!("{:#x}", round)),
+                ];
+                store.get("round", &key)?;
+                let applications: Vec<Application> = store
+                    .rows_with_prefix("application", &key)
+                    .map(|record| Application {
+                        id: record_text(record, "index"),
+                        project_id: record_text(record, "project_id"),
+                        round_id: round,
+                        status: record_text(record, "status"),
+                        votes: record_integer(record, "vote_count") as i32,
+                        unique_contributors: record_integer(record, "unique_contributor_count")
+                            as i32,
+                        metadata: record_json(record, "metadata"),
+                        created_at_block: record_integer(record, "created_at_block"),
+                        status_updated_at_block: record_integer(record, "status_updated_at_block"),
+                        status_snapshots: record_json(record, "status_snapshots"),
+                    })
+                    .collect();
+                to_json(applications)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::Change;
+    use crate::dialect::Dialect;
+    use crate::event_handling::{event_to_changeset_with_metadata, Event, EventPayload};
+    use crate::test_helpers::{connect, event, transaction_with_schema};
+    use ethers::types::{H256, U256};
+    use sea_query::{Alias, IntoIden};
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_path() {
+        let round: Address = "0x5FbDB2315678afecb367f032d93F642f64180aa3"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            Document::parse("/10/rounds.json"),
+            Some(Document::Rounds { chain_id: 10 })
+        );
+        assert_eq!(
+            Document::parse(
+                "/1/rounds/0x5fbdb2315678afecb367f032d93f642f64180aa3/applications.json"
+            ),
+            Some(Document::Applications { chain_id: 1, round })
+        );
+        assert_eq!(
+            Document::Applications { chain_id: 1, round }.path(),
+            "1/rounds/0x5FbDB2315678afecb367f032d93F642f64180aa3/applications.json"
+        );
+        assert_eq!(
+            Document::parse("1/projects.json").unwrap().path(),
+            "1/projects.json"
+        );
+        for path in [
+            "/rounds.json",
+            "/1/rounds",
+            "/1/rounds/0x12/applications.json",
+            "/1/rounds.json/x",
+        ] {
+            assert_eq!(Document::parse(path), None, "{}", path);
+        }
+    }
+
+    #[ignore = "needs Postgres, see GS_INDEX_TEST_DATABASE"]
+    #[tokio::test]
+    async fn test_render() {
+        let mut client = connect().await;
+        let transaction = transaction_with_schema(&mut client).await;
+
+        let round = Address::from_low_u64_be(0xa);
+        let events = [
+            event(
+                1,
+                EventPayload::ProjectCreated {
+                    project_id: U256::from(10),
+                },
+            ),
+            event(
+                2,
+                EventPayload::ProjectCreated {
+                    project_id: U256::from(9),
+                },
+            ),
+            event(
+                2,
+                EventPayload::OwnerAdded {
+                    project_id: U256::from(9),
+                    owner: Address::from_low_u64_be(0xc),
+                },
+            ),
+            event(
+                3,
+                EventPayload::RoundCreated {
+                    round_address: round,
+                },
+            ),
+            Event {
+                address: round,
+                ..event(
+                    4,
+                    EventPayload::NewProjectApplication {
+                        project_id: H256::from_low_u64_be(9),
+                        application_index: U256::zero(),
+                    },
+                )
+            },
+        ];
+        for event in &events {
+            transaction
+                .batch_execute(
+                    &event_to_changeset_with_metadata(event, None).to_sql(Dialect::Postgres),
+                )
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            Document::Rounds { chain_id: 1 }
+                .render(&transaction)
+                .await
+                .unwrap(),
+            Some(
+                json!([{ "id": "0x000000000000000000000000000000000000000A", "createdAtBlock": 3 }])
+            )
+        );
+        assert_eq!(
+            Document::Rounds { chain_id: 10 }
+                .render(&transaction)
+                .await
+                .unwrap(),
+            Some(json!([]))
+        );
+        let projects = Document::Projects { chain_id: 1 }
+            .render(&transaction)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            projects,
+            json!([
+                {
+                    "id": "9",
+                    "metadata": null,
+                    "owners": ["0x000000000000000000000000000000000000000c"],
+                    "createdAtBlock": 2,
+                },
+                { "id": "10", "metadata": null, "owners": [], "createdAtBlock": 1 },
+            ])
+        );
+        assert_eq!(
+            Document::Applications { chain_id: 1, round }
+                .render(&transaction)
+                .await
+                .unwrap(),
+            Some(json!([{
+                "id": "0",
+                "projectId": format!("{:#x}", H256::from_low_u64_be(9)),
+                "roundId": "0x000000000000000000000000000000000000000A",
+                "status": "PENDING",
+                "votes": 0,
+                "uniqueContributors": 0,
+                "metadata": null,
+                "createdAtBlock": 4,
+                "statusUpdatedAtBlock": 4,
+                "statusSnapshots": null,
+            }]))
+        );
+        assert_eq!(
+            Document::Applications {
+                chain_id: 1,
+                round: Address::from_low_u64_be(0xb)
+            }
+            .render(&transaction)
+            .await
+            .unwrap(),
+            None
+        );
+
+        let mut store = Store::new();
+        for event in &events {
+            store
+                .apply_all(&event_to_changeset_with_metadata(event, None).changes)
+                .unwrap();
+        }
+        for document in [
+            Document::Rounds { chain_id: 1 },
+            Document::Rounds { chain_id: 10 },
+            Document::Projects { chain_id: 1 },
+            Document::Applications { chain_id: 1, round },
+            Document::Applications {
+                chain_id: 1,
+                round: Address::from_low_u64_be(0xb),
+            },
+        ] {
+            assert_eq!(
+                document.render_store(&store),
+                document.render(&transaction).await.unwrap(),
+                "{:?}",
+                document
+            );
+        }
+
+        transaction
+            .batch_execute("INSERT INTO round VALUES (1, 'not an address', 5)")
+            .await
+            .unwrap();
+        assert_eq!(
+            Document::Rounds { chain_id: 1 }
+                .render(&transaction)
+                .await
+                .unwrap(),
+            Some(
+                json!([{ "id": "0x000000000000000000000000000000000000000A", "createdAtBlock": 3 }])
+            )
+        );
+    }
+
+    #[test]
+    fn test_render_store_skips_invalid_addresses() {
+        let mut store = Store::new();
+        for round_address in [
+            "0x000000000000000000000000000000000000000a",
+            "not an address",
+        ] {
+            store
+                .apply(&Change::Insert {
+                    table: Alias::new("round").into_iden(),
+                    values: vec![
+                        (Alias::new("chain_id").into_iden(), Value::Integer(1)),
+                        (
+                            Alias::new("round_address").into_iden(),
+                            Value::Text(round_address.to_string()),
+                        ),
+                        (
+                            Alias::new("created_at_block").into_iden(),
+                            Value::Integer(1),
+                        ),
+                    ],
+                })
+                .unwrap();
+        }
+        assert_eq!(
+            Document::Rounds { chain_id: 1 }.render_store(&store),
+            Some(
+                json!([{ "id": "0x000000000000000000000000000000000000000A", "createdAtBlock": 1 }])
+            )
+        );
+    }
+}
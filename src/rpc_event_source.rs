@@ -0,0 +1,841 @@
+use async_stream::stream;
+use ethers::providers::{Middleware, Provider, Ws};
+use ethers::types::{Address, Filter, Log};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use futures::pin_mut;
+use futures::stream::{Stream, StreamExt};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+use crate::contract_watchlist::{ContractWatchlist, WatchedContract, WatchlistCursor};
+use crate::event_handling::Event;
+use crate::event_source::ParseErrorPolicy;
+use crate::log_decoding::{decode_log, supported_event_signatures};
+
+const MAX_ATTEMPTS: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Longest wait between two rounds of retries when the node keeps failing.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+pub struct RpcSourceOptions {
+    pub chain_id: u64,
+    /// Registry, round factory and any round already known. Contracts
+    /// discovered while indexing come through the watchlist instead.
+    pub addresses: Vec<Address>,
+    pub from_block: u64,
+    /// Defaults to the latest block at the time the stream is started.
+    pub to_block: Option<u64>,
+    /// Number of blocks requested with each `eth_getLogs` call. Shrinks when
+    /// the node complains about too many results.
+    pub page_size: u64,
+    pub on_parse_error: ParseErrorPolicy,
+}
+
+/// Fetches logs over a block range with `eth_getLogs`, halving the range
+/// whenever the node refuses to return that many results and growing it back
+/// after successful requests.
+struct LogPager {
+    page_size: u64,
+    max_page_size: u64,
+}
+
+impl LogPager {
+    fn new(page_size: u64) -> Self {
+        LogPager {
+            page_size: page_size.max(1),
+            max_page_size: page_size.max(1),
+        }
+    }
+
+    async fn get_logs<M: Middleware>(
+        &mut self,
+        provider: &M,
+        addresses: &[Address],
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Vec<Log>, String> {
+        let mut logs = vec![];
+        let mut page_start = from_block;
+        while page_start <= to_block {
+            let page_end = to_block.min(page_start.saturating_add(self.page_size - 1));
+            match get_logs_with_retries(provider, addresses, page_start, page_end).await {
+                Ok(mut page) => {
+                    logs.append(&mut page);
+                    page_start = page_end + 1;
+                    self.page_size = (self.page_size * 2).min(self.max_page_size);
+                }
+                Err(err) if self.page_size > 1 && is_range_too_large(&err) => {
+                    self.page_size /= 2;
+                }
+                Err(err) => {
+                    return Err(format!(
+                        "eth_getLogs failed for blocks {}..{}: {}",
+                        page_start, page_end, err
+                    ))
+                }
+            }
+        }
+        Ok(logs)
+    }
+}
+
+async fn get_logs_with_retries<M: Middleware>(
+    provider: &M,
+    addresses: &[Address],
+    from_block: u64,
+    to_block: u64,
+) -> Result<Vec<Log>, String> {
+    let filter = Filter::new()
+        .address(addresses.to_vec())
+        .topic0(supported_event_signatures())
+        .from_block(from_block)
+        .to_block(to_block);
+
+    let mut attempt = 1;
+    loop {
+        match provider.get_logs(&filter).await {
+            Ok(logs) => return Ok(logs),
+            Err(err) => {
+                let message = err.to_string();
+                if attempt == MAX_ATTEMPTS || is_range_too_large(&message) {
+                    return Err(message);
+                }
+                tokio::time::sleep(RETRY_DELAY * attempt).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// How nodes and providers refuse a block range that is too large. Error
+/// codes do not tell: -32005, used by Infura for too many results, is also
+/// their rate limit code.
+const RANGE_TOO_LARGE_MESSAGES: &[&str] = &[
+    // geth, Erigon, Infura
+    "query returned more than",
+    // Alchemy
+    "log response size exceeded",
+    // QuickNode
+    "is limited to a",
+    // Ankr
+    "block range is too wide",
+    // BNB Chain, NodeReal
+    "exceed maximum block range",
+    // Besu
+    "exceeds maximum range limit",
+    // Chainstack
+    "block range limit exceeded",
+];
+
+fn is_range_too_large(message: &str) -> bool {
+    let message = message.to_lowercase();
+    RANGE_TOO_LARGE_MESSAGES
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
+fn decode_logs(chain_id: u64, logs: &[Log], on_parse_error: ParseErrorPolicy) -> Vec<Event> {
+    logs.iter()
+        .filter_map(|log| match decode_log(chain_id, log) {
+            Ok(event) => event,
+            Err(err) => {
+                on_parse_error.handle("log", err);
+                None
+            }
+        })
+        .collect()
+}
+
+/// State shared by the successive block ranges a source fetches: the
+/// contracts being indexed, including those picked up from the watchlist, the
+/// current page size, and the page being returned.
+struct LogSource<M> {
+    provider: M,
+    chain_id: u64,
+    addresses: Vec<Address>,
+    watchlist: ContractWatchlist,
+    watchlist_cursor: WatchlistCursor,
+    /// Whether an event was returned since the watchlist was last checked
+    check_watchlist: bool,
+    /// Contracts picked up from the watchlist and not backfilled yet
+    new_contracts: VecDeque<WatchedContract>,
+    pager: LogPager,
+    on_parse_error: ParseErrorPolicy,
+    /// First block not fetched yet
+    next_block: u64,
+    /// Last block of the current page
+    page_end: u64,
+    /// Events of the current page not returned yet
+    events: VecDeque<Event>,
+}
+
+impl<M: Middleware> LogSource<M> {
+    fn new(provider: M, options: &RpcSourceOptions, watchlist: ContractWatchlist) -> Self {
+        LogSource {
+            provider,
+            chain_id: options.chain_id,
+            addresses: options.addresses.clone(),
+            watchlist,
+            watchlist_cursor: WatchlistCursor::default(),
+            check_watchlist: false,
+            new_contracts: VecDeque::new(),
+            pager: LogPager::new(options.page_size),
+            on_parse_error: options.on_parse_error,
+            next_block: options.from_block,
+            page_end: options.from_block,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Returns the next event up to `to_block`, in block and log index order,
+    /// or `None` once there are no more. Contracts added to the watchlist
+    /// meanwhile are backfilled from the block they were discovered at and
+    /// merged into the current page; any of their events that precede the
+    /// last returned one are returned immediately rather than dropped. After
+    /// an error, calling again resumes where the source stopped.
+    async fn next_event(&mut self, to_block: u64) -> Result<Option<Event>, String> {
+        loop {
+            if self.check_watchlist {
+                let new_contracts = self
+                    .watchlist
+                    .take_new(self.chain_id, &mut self.watchlist_cursor);
+                self.new_contracts.extend(new_contracts);
+                self.check_watchlist = false;
+            }
+            self.backfill_new_contracts().await?;
+
+            if let Some(event) = self.events.pop_front() {
+                self.check_watchlist = true;
+                return Ok(Some(event));
+            }
+            if self.next_block > to_block {
+                return Ok(None);
+            }
+
+            let page_end = to_block.min(self.next_block.saturating_add(self.pager.page_size - 1));
+            let logs = self
+                .pager
+                .get_logs(&self.provider, &self.addresses, self.next_block, page_end)
+                .await?;
+            self.events = decode_logs(self.chain_id, &logs, self.on_parse_error).into();
+            self.sort_events();
+            self.page_end = page_end;
+            self.next_block = page_end + 1;
+        }
+    }
+
+    /// Fetches the logs of the new contracts up to the end of the current
+    /// page. A contract is only followed once its logs have been fetched.
+    async fn backfill_new_contracts(&mut self) -> Result<(), String> {
+        while let Some(contract) = self.new_contracts.front() {
+            let (address, from_block) = (contract.address, contract.from_block);
+            if !self.addresses.contains(&address) {
+                let logs = self
+                    .pager
+                    .get_logs(&self.provider, &[address], from_block, self.page_end)
+                    .await?;
+                self.addresses.push(address);
+                self.events
+                    .extend(decode_logs(self.chain_id, &logs, self.on_parse_error));
+                self.sort_events();
+            }
+            self.new_contracts.pop_front();
+        }
+        Ok(())
+    }
+
+    fn sort_events(&mut self) {
+        self.events
+            .make_contiguous()
+            .sort_by_key(|event| (event.block_number, event.log_index));
+    }
+
+    /// Like `next_event`, but retries failed requests until they succeed,
+    /// waiting longer each time.
+    async fn next_event_retrying(&mut self, to_block: u64) -> Option<Event> {
+        let mut delay = RETRY_DELAY;
+        loop {
+            match self.next_event(to_block).await {
+                Ok(event) => return event,
+                Err(err) => back_off(&err, &mut delay).await,
+            }
+        }
+    }
+}
+
+/// Waits `delay` after `err` before trying again, and doubles `delay` for
+/// the next time, up to `MAX_RETRY_DELAY`.
+async fn back_off(err: &str, delay: &mut Duration) {
+    eprintln!("Warning: {}, retrying in {}s", err, delay.as_secs());
+    tokio::time::sleep(*delay).await;
+    *delay = (*delay * 2).min(MAX_RETRY_DELAY);
+}
+
+async fn get_latest_block_number<M: Middleware>(provider: &M) -> Result<u64, String> {
+    provider
+        .get_block_number()
+        .await
+        .map(|number| number.as_u64())
+        .map_err(|err| format!("cannot get latest block number: {}", err))
+}
+
+async fn get_latest_block_number_retrying<M: Middleware>(provider: &M) -> u64 {
+    let mut delay = RETRY_DELAY;
+    loop {
+        match get_latest_block_number(provider).await {
+            Ok(number) => return number,
+            Err(err) => back_off(&err, &mut delay).await,
+        }
+    }
+}
+
+/// Streams events from a JSON-RPC node using `eth_getLogs`. Requests that
+/// keep failing are retried with growing delays rather than ending the
+/// stream.
+pub fn event_stream_from_rpc<M: Middleware>(
+    provider: M,
+    options: RpcSourceOptions,
+    watchlist: ContractWatchlist,
+) -> impl Stream<Item = (Event, usize)> {
+    stream! {
+        let to_block = match options.to_block {
+            Some(to_block) => to_block,
+            None => get_latest_block_number_retrying(&provider).await,
+        };
+
+        let mut source = LogSource::new(provider, &options, watchlist);
+        let mut index = 0;
+        while let Some(event) = source.next_event_retrying(to_block).await {
+            yield (event, index);
+            index += 1;
+        }
+    }
+}
+
+/// Fetches the events up to the latest block, then those of every new
+/// block number coming from `heads`. Heads that are not past the last
+/// indexed block are ignored. Ends with an error at the first failed
+/// request, or once `heads` does; `source` can then go on with new heads.
+fn follow_heads<'a, M: Middleware>(
+    source: &'a mut LogSource<M>,
+    heads: impl Stream<Item = u64> + 'a,
+) -> impl Stream<Item = Result<Event, String>> + 'a {
+    stream! {
+        let latest_block = match get_latest_block_number(&source.provider).await {
+            Ok(latest_block) => latest_block,
+            Err(err) => {
+                yield Err(err);
+                return;
+            }
+        };
+        let heads = futures::stream::once(async move { latest_block }).chain(heads);
+
+        pin_mut!(heads);
+        while let Some(head) = heads.next().await {
+            loop {
+                match source.next_event(head).await {
+                    Ok(Some(event)) => yield Ok(event),
+                    Ok(None) => break,
+                    Err(err) => {
+                        yield Err(err);
+                        return;
+                    }
+                }
+            }
+        }
+        yield Err("subscription to new blocks ended".to_string());
+    }
+}
+
+/// Connects to a WebSocket endpoint and subscribes to new blocks, retrying
+/// until both succeed. Returns the provider and the number of each new
+/// block, until the subscription ends.
+async fn subscribe_to_heads(
+    url: &str,
+    delay: &mut Duration,
+) -> (Provider<Ws>, UnboundedReceiver<u64>) {
+    loop {
+        match try_subscribe_to_heads(url).await {
+            Ok(subscription) => return subscription,
+            Err(err) => back_off(&err, delay).await,
+        }
+    }
+}
+
+async fn try_subscribe_to_heads(
+    url: &str,
+) -> Result<(Provider<Ws>, UnboundedReceiver<u64>), String> {
+    let provider = Provider::<Ws>::connect(url)
+        .await
+        .map_err(|err| format!("cannot connect to {}: {}", url, err))?;
+    // The subscription borrows the provider, so it is read by a task of its
+    // own, which stops when the subscription ends or nobody listens.
+    let subscription_provider = provider.clone();
+    let (subscribed, subscription) = oneshot::channel();
+    let (sender, heads) = unbounded();
+    tokio::spawn(async move {
+        let blocks = match subscription_provider.subscribe_blocks().await {
+            Ok(blocks) => blocks,
+            Err(err) => {
+                let _ = subscribed.send(Err(format!("cannot subscribe to new blocks: {}", err)));
+                return;
+            }
+        };
+        let _ = subscribed.send(Ok(()));
+        let numbers = blocks.filter_map(|block| async move { block.number });
+        pin_mut!(numbers);
+        while let Some(number) = numbers.next().await {
+            if sender.unbounded_send(number.as_u64()).is_err() {
+                return;
+            }
+        }
+    });
+    subscription
+        .await
+        .map_err(|_| "subscription to new blocks ended".to_string())??;
+    Ok((provider, heads))
+}
+
+/// Streams events from a WebSocket JSON-RPC endpoint: backfills up to the
+/// latest block, then follows new blocks through an `eth_subscribe`
+/// subscription. When the connection fails, connects again and resumes
+/// after the last event, waiting longer each time if the connection keeps
+/// failing right away.
+pub fn event_stream_from_ws(
+    url: String,
+    options: RpcSourceOptions,
+    watchlist: ContractWatchlist,
+) -> impl Stream<Item = (Event, usize)> {
+    stream! {
+        let mut delay = RETRY_DELAY;
+        let (provider, mut heads) = subscribe_to_heads(&url, &mut delay).await;
+        let mut source = LogSource::new(provider, &options, watchlist);
+        let mut index = 0;
+
+        loop {
+            let connected_at = Instant::now();
+            let mut error = String::new();
+            {
+                let events = follow_heads(&mut source, heads);
+                pin_mut!(events);
+                while let Some(item) = events.next().await {
+                    match item {
+                        Ok(event) => {
+                            yield (event, index);
+                            index += 1;
+                        }
+                        Err(err) => error = err,
+                    }
+                }
+            }
+
+            if connected_at.elapsed() > MAX_RETRY_DELAY {
+                delay = RETRY_DELAY;
+            }
+            back_off(&error, &mut delay).await;
+            let (provider, new_heads) = subscribe_to_heads(&url, &mut delay).await;
+            source.provider = provider;
+            heads = new_heads;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handling::{event_to_changeset, EventPayload, MetadataError};
+    use ethers::abi::{encode, Token};
+    use ethers::providers::{MockProvider, MockResponse, Provider};
+    use ethers::types::U64;
+    use ethers::types::{H256, U256};
+    use ethers::utils::keccak256;
+    use serde_json::json;
+    use std::pin::Pin;
+
+    const REGISTRY: u64 = 0x1;
+    const ROUND_FACTORY: u64 = 0x2;
+    const ROUND: u64 = 0x3;
+
+    fn make_log(
+        address: u64,
+        signature: &str,
+        topics: Vec<H256>,
+        data: Vec<Token>,
+        block_number: u64,
+        log_index: u64,
+    ) -> Log {
+        Log {
+            address: Address::from_low_u64_be(address),
+            topics: [vec![H256::from(keccak256(signature))], topics].concat(),
+            data: encode(&data).into(),
+            block_number: Some(block_number.into()),
+            log_index: Some(log_index.into()),
+            ..Default::default()
+        }
+    }
+
+    fn project_created(block_number: u64, log_index: u64) -> Log {
+        make_log(
+            REGISTRY,
+            "ProjectCreated(uint256,address)",
+            vec![H256::from_low_u64_be(block_number), H256::zero()],
+            vec![],
+            block_number,
+            log_index,
+        )
+    }
+
+    fn round_created(block_number: u64, log_index: u64) -> Log {
+        make_log(
+            ROUND_FACTORY,
+            "RoundCreated(address,address,address)",
+            vec![
+                H256::from(Address::from_low_u64_be(ROUND)),
+                H256::zero(),
+                H256::zero(),
+            ],
+            vec![],
+            block_number,
+            log_index,
+        )
+    }
+
+    fn new_project_application(block_number: u64, log_index: u64) -> Log {
+        make_log(
+            ROUND,
+            "NewProjectApplication(bytes32,uint256,(uint256,string))",
+            vec![H256::from_low_u64_be(0x789)],
+            vec![
+                Token::Uint(U256::zero()),
+                Token::Tuple(vec![
+                    Token::Uint(U256::one()),
+                    Token::String("bafkreih".to_string()),
+                ]),
+            ],
+            block_number,
+            log_index,
+        )
+    }
+
+    fn dummy_ipfs_getter(
+        _url: String,
+    ) -> Pin<Box<dyn futures::Future<Output = Result<String, MetadataError>> + Send>> {
+        Box::pin(async move { Ok(r#"{ "foo": "bar" }"#.to_string()) })
+    }
+
+    fn mocked_provider(responses: Vec<MockResponse>) -> (Provider<MockProvider>, MockProvider) {
+        let (provider, mock) = Provider::mocked();
+        // MockProvider answers with the most recently pushed response first
+        for response in responses.into_iter().rev() {
+            mock.push_response(response);
+        }
+        (provider, mock)
+    }
+
+    fn logs_response(logs: Vec<Log>) -> MockResponse {
+        MockResponse::Value(serde_json::to_value(logs).unwrap())
+    }
+
+    fn options(from_block: u64, to_block: u64, page_size: u64) -> RpcSourceOptions {
+        RpcSourceOptions {
+            chain_id: 1,
+            addresses: vec![
+                Address::from_low_u64_be(REGISTRY),
+                Address::from_low_u64_be(ROUND_FACTORY),
+            ],
+            from_block,
+            to_block: Some(to_block),
+            page_size,
+            on_parse_error: ParseErrorPolicy::Ignore,
+        }
+    }
+
+    fn assert_get_logs_request(mock: &MockProvider, addresses: &[u64], from: u64, to: u64) {
+        let addresses: Vec<Address> = addresses
+            .iter()
+            .map(|address| Address::from_low_u64_be(*address))
+            .collect();
+        let filter = Filter::new()
+            .address(addresses)
+            .topic0(supported_event_signatures())
+            .from_block(from)
+            .to_block(to);
+        mock.assert_request("eth_getLogs", [filter]).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_event_stream_from_rpc_pages_block_range() {
+        let (provider, mock) = mocked_provider(vec![
+            logs_response(vec![project_created(10, 0), project_created(15, 3)]),
+            logs_response(vec![project_created(20, 1)]),
+        ]);
+
+        let rpc_event_source =
+            event_stream_from_rpc(provider, options(10, 24, 10), ContractWatchlist::new());
+        pin_mut!(rpc_event_source);
+        let events: Vec<(Event, usize)> = rpc_event_source.collect().await;
+
+        let positions: Vec<(u64, u64, usize)> = events
+            .iter()
+            .map(|(event, index)| (event.block_number, event.log_index, *index))
+            .collect();
+        assert_eq!(positions, vec![(10, 0, 0), (15, 3, 1), (20, 1, 2)]);
+
+        assert_get_logs_request(&mock, &[REGISTRY, ROUND_FACTORY], 10, 19);
+        assert_get_logs_request(&mock, &[REGISTRY, ROUND_FACTORY], 20, 24);
+    }
+
+    #[tokio::test]
+    async fn test_event_stream_from_rpc_shrinks_range_on_too_many_results() {
+        let too_many_results = MockResponse::Error(
+            serde_json::from_value(json!({
+                "code": -32005,
+                "message": "query returned more than 10000 results"
+            }))
+            .unwrap(),
+        );
+        let (provider, mock) = mocked_provider(vec![
+            too_many_results,
+            logs_response(vec![project_created(12, 0)]),
+            logs_response(vec![project_created(16, 0)]),
+        ]);
+
+        let rpc_event_source =
+            event_stream_from_rpc(provider, options(10, 17, 8), ContractWatchlist::new());
+        pin_mut!(rpc_event_source);
+        let events: Vec<(Event, usize)> = rpc_event_source.collect().await;
+
+        assert_eq!(events.len(), 2);
+        assert_get_logs_request(&mock, &[REGISTRY, ROUND_FACTORY], 10, 17);
+        assert_get_logs_request(&mock, &[REGISTRY, ROUND_FACTORY], 10, 13);
+        assert_get_logs_request(&mock, &[REGISTRY, ROUND_FACTORY], 14, 17);
+    }
+
+    #[tokio::test]
+    async fn test_log_source_resumes_after_errors() {
+        // With single-block pages, a range error is not retried
+        let too_many_results = || {
+            MockResponse::Error(
+                serde_json::from_value(json!({
+                    "code": -32005,
+                    "message": "query returned more than 10000 results"
+                }))
+                .unwrap(),
+            )
+        };
+        let (provider, mock) = mocked_provider(vec![
+            logs_response(vec![round_created(10, 0)]),
+            too_many_results(),
+            logs_response(vec![new_project_application(10, 1)]),
+            too_many_results(),
+            logs_response(vec![project_created(11, 0)]),
+        ]);
+
+        let watchlist = ContractWatchlist::new();
+        let mut source = LogSource::new(provider, &options(10, 11, 1), watchlist.clone());
+        let mut positions = vec![];
+        let mut errors = 0;
+        loop {
+            match source.next_event(11).await {
+                Ok(Some(event)) => {
+                    let change_set = event_to_changeset(&event, dummy_ipfs_getter).await;
+                    watchlist.register(&event, &change_set.discovered_contracts);
+                    positions.push((event.block_number, event.log_index));
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    assert!(err.starts_with("eth_getLogs failed for blocks"), "{}", err);
+                    errors += 1;
+                }
+            }
+        }
+
+        assert_eq!(errors, 2);
+        assert_eq!(positions, vec![(10, 0), (10, 1), (11, 0)]);
+        assert_get_logs_request(&mock, &[REGISTRY, ROUND_FACTORY], 10, 10);
+        assert_get_logs_request(&mock, &[ROUND], 10, 10);
+        assert_get_logs_request(&mock, &[ROUND], 10, 10);
+        assert_get_logs_request(&mock, &[REGISTRY, ROUND_FACTORY, ROUND], 11, 11);
+        assert_get_logs_request(&mock, &[REGISTRY, ROUND_FACTORY, ROUND], 11, 11);
+    }
+
+    #[tokio::test]
+    async fn test_event_stream_from_rpc_follows_discovered_contracts() {
+        let (provider, mock) = mocked_provider(vec![
+            logs_response(vec![round_created(12, 4), project_created(14, 0)]),
+            logs_response(vec![
+                new_project_application(12, 7),
+                new_project_application(17, 0),
+            ]),
+            logs_response(vec![new_project_application(21, 0)]),
+        ]);
+
+        let watchlist = ContractWatchlist::new();
+        let rpc_event_source =
+            event_stream_from_rpc(provider, options(10, 29, 10), watchlist.clone());
+        pin_mut!(rpc_event_source);
+
+        let mut events = vec![];
+        while let Some((event, index)) = rpc_event_source.next().await {
+            let change_set = event_to_changeset(&event, dummy_ipfs_getter).await;
+            watchlist.register(&event, &change_set.discovered_contracts);
+            events.push((event, index));
+        }
+
+        let positions: Vec<(u64, u64)> = events
+            .iter()
+            .map(|(event, _)| (event.block_number, event.log_index))
+            .collect();
+        assert_eq!(positions, vec![(12, 4), (12, 7), (14, 0), (17, 0), (21, 0)]);
+        assert!(matches!(
+            events[1].0.data,
+            EventPayload::NewProjectApplication { .. }
+        ));
+
+        assert_get_logs_request(&mock, &[REGISTRY, ROUND_FACTORY], 10, 19);
+        assert_get_logs_request(&mock, &[ROUND], 12, 19);
+        assert_get_logs_request(&mock, &[REGISTRY, ROUND_FACTORY, ROUND], 20, 29);
+    }
+
+    #[tokio::test]
+    async fn test_follow_heads_backfills_then_follows_heads() {
+        let (provider, mock) = mocked_provider(vec![
+            MockResponse::Value(serde_json::to_value(U64::from(20)).unwrap()),
+            logs_response(vec![project_created(10, 0), project_created(20, 1)]),
+            logs_response(vec![project_created(22, 0)]),
+            logs_response(vec![project_created(23, 0), project_created(25, 2)]),
+        ]);
+
+        let heads = futures::stream::iter(vec![20, 22, 21, 25]);
+        let mut source = LogSource::new(provider, &options(10, 0, 100), ContractWatchlist::new());
+        let events: Vec<Result<Event, String>> = follow_heads(&mut source, heads).collect().await;
+
+        let positions: Vec<(u64, u64)> = events
+            .iter()
+            .filter_map(|event| event.as_ref().ok())
+            .map(|event| (event.block_number, event.log_index))
+            .collect();
+        assert_eq!(positions, vec![(10, 0), (20, 1), (22, 0), (23, 0), (25, 2)]);
+        assert_eq!(
+            events.last().unwrap().as_ref().unwrap_err(),
+            "subscription to new blocks ended"
+        );
+
+        mock.assert_request("eth_blockNumber", ()).unwrap();
+        assert_get_logs_request(&mock, &[REGISTRY, ROUND_FACTORY], 10, 20);
+        assert_get_logs_request(&mock, &[REGISTRY, ROUND_FACTORY], 21, 22);
+        assert_get_logs_request(&mock, &[REGISTRY, ROUND_FACTORY], 23, 25);
+
+        // Following again, as after reconnecting, resumes after block 25
+        let (provider, mock) = mocked_provider(vec![
+            MockResponse::Value(serde_json::to_value(U64::from(27)).unwrap()),
+            logs_response(vec![project_created(27, 0)]),
+        ]);
+        source.provider = provider;
+        let events: Vec<Result<Event, String>> =
+            follow_heads(&mut source, futures::stream::empty())
+                .collect()
+                .await;
+        assert_eq!(events[0].as_ref().unwrap().block_number, 27);
+        mock.assert_request("eth_blockNumber", ()).unwrap();
+        assert_get_logs_request(&mock, &[REGISTRY, ROUND_FACTORY], 26, 27);
+    }
+
+    #[test]
+    fn test_is_range_too_large() {
+        for message in [
+            "(code: -32005, message: query returned more than 10000 results, data: None)",
+            "Log response size exceeded. You can make eth_getLogs requests with up to a 2K block range and no limit on the response size",
+            "eth_getLogs is limited to a 10,000 range",
+            "block range is too wide",
+            "exceed maximum block range: 5000",
+            "Requested range exceeds maximum range limit",
+            "Block range limit exceeded",
+        ] {
+            assert!(is_range_too_large(message), "{}", message);
+        }
+        for message in [
+            "(code: -32005, message: daily request count exceeded, request rate limited, data: None)",
+            "invalid block range params",
+            "header not found",
+            "out of range",
+            "more than one filter given",
+        ] {
+            assert!(!is_range_too_large(message), "{}", message);
+        }
+    }
+
+    /// Runs against a local anvil node, and is skipped when anvil is not
+    /// installed.
+    #[tokio::test]
+    async fn test_event_stream_from_anvil() {
+        use ethers::providers::Http;
+        use ethers::types::TransactionRequest;
+        use ethers::utils::Anvil;
+
+        if std::process::Command::new("anvil")
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            eprintln!("skipping test_event_stream_from_anvil: anvil is not installed");
+            return;
+        }
+        let anvil = Anvil::new().spawn();
+        let provider = Provider::<Http>::try_from(anvil.endpoint()).unwrap();
+        let registry = Address::from_low_u64_be(REGISTRY);
+        // Emits a log with the three words of the calldata as topics:
+        // LOG3(0, 0, calldata[0], calldata[1], calldata[2])
+        provider
+            .request::<_, ()>(
+                "anvil_setCode",
+                (registry, "0x60403560203560003560006000a300"),
+            )
+            .await
+            .unwrap();
+        let owner = anvil.addresses()[0];
+        for project_id in 1..=3 {
+            let topics = [
+                H256::from(keccak256("ProjectCreated(uint256,address)")),
+                H256::from_low_u64_be(project_id),
+                H256::from(owner),
+            ];
+            let transaction = TransactionRequest::new()
+                .from(owner)
+                .to(registry)
+                .data(topics.iter().flat_map(|topic| topic.0).collect::<Vec<u8>>());
+            provider
+                .send_transaction(transaction, None)
+                .await
+                .unwrap()
+                .await
+                .unwrap();
+        }
+        let latest_block = provider.get_block_number().await.unwrap().as_u64();
+
+        let options = RpcSourceOptions {
+            chain_id: anvil.chain_id(),
+            addresses: vec![registry],
+            from_block: 0,
+            to_block: Some(latest_block),
+            page_size: 2,
+            on_parse_error: ParseErrorPolicy::Panic,
+        };
+        let rpc_event_source = event_stream_from_rpc(provider, options, ContractWatchlist::new());
+        pin_mut!(rpc_event_source);
+        let events: Vec<(Event, usize)> = rpc_event_source.collect().await;
+
+        let project_ids: Vec<U256> = events
+            .iter()
+            .map(|(event, _index)| match event.data {
+                EventPayload::ProjectCreated { project_id } => project_id,
+                _ => panic!("unexpected event {:?}", event),
+            })
+            .collect();
+        assert_eq!(project_ids, [1, 2, 3].map(U256::from));
+        assert!(events
+            .windows(2)
+            .all(|pair| pair[0].0.block_number < pair[1].0.block_number));
+    }
+}
@@ -0,0 +1,123 @@
+use tokio_postgres::{Error, GenericClient};
+
+#[derive(Debug, PartialEq)]
+pub struct ProjectMatch {
+    pub chain_id: i64,
+    pub project_id: String,
+    pub title: String,
+}
+
+/// `tsquery` matching documents that contain words starting with each word
+/// of `query`, so that partial names match. Returns `None` when `query` has
+/// no words.
+fn prefix_query(query: &str) -> Option<String> {
+    let words: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("{}:*", word.to_lowercase()))
+        .collect();
+    (!words.is_empty()).then(|| words.join(" & "))
+}
+
+/// Projects whose title or description match `query`, best matches first.
+pub async fn search_projects(
+    client: &impl GenericClient,
+    query: &str,
+    chain_id: Option<u64>,
+    limit: i64,
+) -> Result<Vec<ProjectMatch>, Error> {
+    let Some(query) = prefix_query(query) else {
+        return Ok(vec![]);
+    };
+    let chain_id = chain_id.map(|chain_id| chain_id as i64);
+    let rows = client
+        .query(
+            "SELECT chain_id, project_id::TEXT, title FROM project, to_tsquery('simple', $1) query WHERE search @@ query AND ($2::BIGINT IS NULL OR chain_id = $2) ORDER BY ts_rank(search, query) DESC, chain_id, project_id LIMIT $3",
+            &[&query, &chain_id, &limit],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| ProjectMatch {
+            chain_id: row.get(0),
+            project_id: row.get(1),
+            title: row.get(2),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handling::project_metadata_sql;
+    use crate::test_helpers::{connect, transaction_with_schema};
+    use ethers::types::U256;
+
+    #[test]
+    fn test_prefix_query() {
+        assert_eq!(
+            prefix_query("Gitcoin  Grants!"),
+            Some("gitcoin:* & grants:*".to_string())
+        );
+        assert_eq!(prefix_query("' & !"), None);
+    }
+
+    #[ignore = "needs Postgres, see GS_INDEX_TEST_DATABASE"]
+    #[tokio::test]
+    async fn test_search_projects() {
+        let mut client = connect().await;
+        let transaction = transaction_with_schema(&mut client).await;
+
+        for (chain_id, project_id, title, description) in [
+            (1, 1, "Gitcoin Grants", "Funding public goods"),
+            (1, 2, "Public Goods Podcast", "Interviews with grantees"),
+            (10, 3, "Grantee Tracker", "Tracks public goods funding"),
+        ] {
+            transaction
+                .batch_execute(&format!(
+                    "INSERT INTO project (chain_id, project_id, created_at_block) VALUES ({}, {}, 1); {}",
+                    chain_id,
+                    project_id,
+                    project_metadata_sql(
+                        chain_id,
+                        &U256::from(project_id),
+                        "cid",
+                        Ok(serde_json::json!({"title": title, "description": description}).to_string())
+                    )
+                ))
+                .await
+                .unwrap();
+        }
+
+        let titles = |matches: Vec<ProjectMatch>| -> Vec<String> {
+            matches.into_iter().map(|found| found.title).collect()
+        };
+        // Title matches rank above description matches
+        assert_eq!(
+            titles(
+                search_projects(&transaction, "grant", None, 10)
+                    .await
+                    .unwrap()
+            ),
+            vec!["Gitcoin Grants", "Grantee Tracker", "Public Goods Podcast"]
+        );
+        assert_eq!(
+            search_projects(&transaction, "public goods", Some(10), 10)
+                .await
+                .unwrap(),
+            vec![ProjectMatch {
+                chain_id: 10,
+                project_id: "3".to_string(),
+                title: "Grantee Tracker".to_string(),
+            }]
+        );
+        assert_eq!(
+            titles(
+                search_projects(&transaction, "podcast", None, 10)
+                    .await
+                    .unwrap()
+            ),
+            vec!["Public Goods Podcast"]
+        );
+    }
+}
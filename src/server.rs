@@ -0,0 +1,231 @@
+//! HTTP server for `gs-index serve`.
+
+use hyper::body::{Bytes, HttpBody};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::convert::Infallible;
+use std::io;
+use std::net::TcpListener;
+use std::sync::Arc;
+use tokio_postgres::Client;
+
+use crate::graphql::{self, ApiSchema};
+use crate::rest::Document;
+
+/// Largest GraphQL request read, far above any real query.
+const MAX_BODY_SIZE: usize = 1 << 20;
+
+#[derive(Clone)]
+struct Api {
+    schema: ApiSchema,
+    /// Serves the static JSON layout too
+    rest: Option<Arc<Client>>,
+}
+
+/// Answers GraphQL queries POSTed to `/graphql` and, with `rest`, GET
+/// requests for the documents of the static JSON layout, until the process
+/// exits.
+pub async fn serve(listener: TcpListener, client: Arc<Client>, rest: bool) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let api = Api {
+        schema: graphql::schema(client.clone()),
+        rest: rest.then_some(client),
+    };
+    let make_service = make_service_fn(move |_connection| {
+        let api = api.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let api = api.clone();
+                async move { Ok::<_, Infallible>(handle(&api, request).await) }
+            }))
+        }
+    });
+    Server::from_tcp(listener)
+        .map_err(io::Error::other)?
+        .serve(make_service)
+        .await
+        .map_err(io::Error::other)
+}
+
+async fn handle(api: &Api, request: Request<Body>) -> Response<Body> {
+    match (request.method(), request.uri().path(), &api.rest) {
+        (&Method::POST, "/graphql", _) => graphql(&api.schema, request).await,
+        (&Method::GET, path, Some(client)) => match Document::parse(path) {
+            Some(document) => match document.render(client.as_ref()).await {
+                Ok(Some(content)) => json(StatusCode::OK, &content),
+                Ok(None) => text(StatusCode::NOT_FOUND, "not found"),
+                Err(err) => text(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+            },
+            None => text(StatusCode::NOT_FOUND, "not found"),
+        },
+        _ => text(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+fn text(status: StatusCode, body: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "text/plain; charset=utf-8")
+        .body(Body::from(body.to_string()))
+        .expect("valid response")
+}
+
+fn json(status: StatusCode, body: &impl serde::Serialize) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(
+            serde_json::to_string(body).expect("responses can be serialized"),
+        ))
+        .expect("valid response")
+}
+
+/// Reads a request body of at most `max_size` bytes, or the response to
+/// send instead. A larger body is refused without reading the rest of it.
+pub async fn read_body(mut body: Body, max_size: usize) -> Result<Bytes, Response<Body>> {
+    let too_large = || {
+        text(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            &format!("request body larger than {} bytes", max_size),
+        )
+    };
+    if body.size_hint().lower() > max_size as u64 {
+        return Err(too_large());
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| text(StatusCode::BAD_REQUEST, &err.to_string()))?;
+        if bytes.len() + chunk.len() > max_size {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes.into())
+}
+
+async fn graphql(schema: &ApiSchema, request: Request<Body>) -> Response<Body> {
+    let body = match read_body(request.into_body(), MAX_BODY_SIZE).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let request: async_graphql::Request = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(err) => return text(StatusCode::BAD_REQUEST, &err.to_string()),
+    };
+    json(StatusCode::OK, &schema.execute(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::client_with_schema;
+    use serde_json::json;
+
+    /// Serves a database with a single round, in a transaction that is
+    /// never committed. Returns the root URL.
+    async fn start_server(rest: bool) -> String {
+        let client = client_with_schema().await;
+        client
+            .batch_execute(
+                "INSERT INTO round VALUES (1, '0x000000000000000000000000000000000000000a', 1)",
+            )
+            .await
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, Arc::new(client), rest));
+        url
+    }
+
+    #[tokio::test]
+    async fn test_read_body() {
+        assert_eq!(read_body(Body::from("abc"), 3).await.unwrap(), "abc");
+        let response = read_body(Body::from("abcd"), 3).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        // Without a length known upfront
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            for chunk in ["ab", "cd"] {
+                sender.send_data(chunk.into()).await.unwrap();
+            }
+        });
+        let response = read_body(body, 3).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[ignore = "needs Postgres, see GS_INDEX_TEST_DATABASE"]
+    #[tokio::test]
+    async fn test_serve_graphql() {
+        let url = start_server(false).await;
+        let http = reqwest::Client::new();
+        let response = http
+            .post(format!("{}/graphql", url))
+            .json(&json!({ "query": "{ rounds { chainId roundAddress } }" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.json::<serde_json::Value>().await.unwrap(),
+            json!({ "data": { "rounds": [{
+                "chainId": 1,
+                "roundAddress": "0x000000000000000000000000000000000000000a",
+            }] } })
+        );
+
+        let response = http
+            .post(format!("{}/graphql", url))
+            .body("not json")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        let response = http
+            .post(format!("{}/graphql", url))
+            .body(vec![b' '; MAX_BODY_SIZE + 1])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 413);
+        let response = http.get(format!("{}/other", url)).send().await.unwrap();
+        assert_eq!(response.status(), 404);
+        let response = http
+            .get(format!("{}/1/rounds.json", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[ignore = "needs Postgres, see GS_INDEX_TEST_DATABASE"]
+    #[tokio::test]
+    async fn test_serve_rest() {
+        let url = start_server(true).await;
+        let response = reqwest::get(format!("{}/1/rounds.json", url))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.json::<serde_json::Value>().await.unwrap(),
+            json!([{ "id": "0x000000000000000000000000000000000000000A", "createdAtBlock": 1 }])
+        );
+        let response = reqwest::get(format!(
+            "{}/1/rounds/0x000000000000000000000000000000000000000a/applications.json",
+            url
+        ))
+        .await
+        .unwrap();
+        assert_eq!(
+            response.json::<serde_json::Value>().await.unwrap(),
+            json!([])
+        );
+        for path in [
+            "/1/rounds/0x000000000000000000000000000000000000000b/applications.json",
+            "/1/unknown.json",
+        ] {
+            let response = reqwest::get(format!("{}{}", url, path)).await.unwrap();
+            assert_eq!(response.status(), 404, "{}", path);
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use rusqlite::Connection;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::changes::Change;
+use crate::config::{Config, OutputFormat, SinkConfig};
+use crate::copy_sink::CopySink;
+use crate::dialect::{Dialect, SQLITE_SCHEMA};
+use crate::event_handling::{changes_to_sql, Event};
+use crate::json_sink::JsonSink;
+
+/// Where the changes built from the events go.
+pub trait Sink {
+    /// Writes the statements creating the tables, outside of any batch.
+    fn write_schema(&mut self) -> io::Result<()>;
+
+    /// Writes the changes of one event.
+    fn write_changes(&mut self, event: &Event, changes: &[Change]) -> io::Result<()>;
+
+    /// Writes the changes of several events in a transaction of their own,
+    /// whatever the batch size, after committing the pending batch. None of
+    /// them is applied if this fails. Sinks that can undo the changes fail
+    /// with `io::ErrorKind::InvalidData` when they refuse them, for example
+    /// on a duplicate key, and can keep being written to.
+    fn write_atomically(&mut self, change_sets: &[(Event, Vec<Change>)]) -> io::Result<()>;
+
+    /// Whether a transaction has been opened and not committed yet.
+    fn is_in_batch(&self) -> bool;
+
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Commits the pending batch, if any, and flushes.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+pub fn open_sink(config: &Config) -> io::Result<Box<dyn Sink>> {
+    let batch_size = config.batching.size;
+    let writer: Box<dyn Write> = match &config.sink {
+        SinkConfig::Stdout => Box::new(io::stdout()),
+        SinkConfig::File { path } => Box::new(BufWriter::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        )),
+        SinkConfig::Sqlite { path } => return Ok(Box::new(SqliteSink::open(path, batch_size)?)),
+    };
+    Ok(if config.output.format == OutputFormat::Json {
+        Box::new(JsonSink::new(writer))
+    } else if config.batching.copy {
+        Box::new(CopySink::new(writer, batch_size))
+    } else {
+        Box::new(SqlSink::new(writer, config.dialect(), batch_size))
+    })
+}
+
+/// Writes SQL statements, optionally grouping them in transactions of
+/// `batch_size` statements so that a consumer piping them into a database
+/// never applies part of a batch.
+pub struct SqlSink<W: Write> {
+    writer: W,
+    dialect: Dialect,
+    batch_size: usize,
+    statements_in_batch: usize,
+}
+
+impl<W: Write> SqlSink<W> {
+    pub fn new(writer: W, dialect: Dialect, batch_size: usize) -> Self {
+        SqlSink {
+            writer,
+            dialect,
+            batch_size,
+            statements_in_batch: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Writes `sql` as is, outside of any batch.
+    pub fn write_raw(&mut self, sql: &str) -> io::Result<()> {
+        writeln!(self.writer, "{}", sql)
+    }
+
+    pub fn write_statement(&mut self, sql: &str) -> io::Result<()> {
+        if self.batch_size > 0 && self.statements_in_batch == 0 {
+            writeln!(self.writer, "BEGIN;")?;
+        }
+        writeln!(self.writer, "{};", sql)?;
+        if self.batch_size > 0 {
+            self.statements_in_batch += 1;
+            if self.statements_in_batch == self.batch_size {
+                writeln!(self.writer, "COMMIT;")?;
+                self.statements_in_batch = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Sink for SqlSink<W> {
+    fn write_schema(&mut self) -> io::Result<()> {
+        self.write_raw(self.dialect.schema())
+    }
+
+    fn write_changes(&mut self, _event: &Event, changes: &[Change]) -> io::Result<()> {
+        self.write_statement(&changes_to_sql(changes, self.dialect))
+    }
+
+    /// A consumer that never reads the COMMIT, because writing stopped
+    /// before it, rolls the transaction back.
+    fn write_atomically(&mut self, change_sets: &[(Event, Vec<Change>)]) -> io::Result<()> {
+        self.finish()?;
+        writeln!(self.writer, "BEGIN;")?;
+        for (_event, changes) in change_sets {
+            writeln!(self.writer, "{};", changes_to_sql(changes, self.dialect))?;
+        }
+        writeln!(self.writer, "COMMIT;")
+    }
+
+    fn is_in_batch(&self) -> bool {
+        self.statements_in_batch > 0
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.is_in_batch() {
+            writeln!(self.writer, "COMMIT;")?;
+            self.statements_in_batch = 0;
+        }
+        self.flush()
+    }
+}
+
+/// Applies the statements to a SQLite database file, creating it and its
+/// tables if needed. Batches are applied in transactions, which also makes
+/// writing much faster than one implicit transaction per statement.
+pub struct SqliteSink {
+    connection: Connection,
+    batch_size: usize,
+    statements_in_batch: usize,
+}
+
+impl SqliteSink {
+    pub fn open(path: &Path, batch_size: usize) -> io::Result<Self> {
+        let connection = Connection::open(path).map_err(io::Error::other)?;
+        connection
+            .execute_batch(SQLITE_SCHEMA)
+            .map_err(io::Error::other)?;
+        Ok(SqliteSink {
+            connection,
+            batch_size,
+            statements_in_batch: 0,
+        })
+    }
+
+    fn execute(&self, sql: &str) -> io::Result<()> {
+        self.connection
+            .execute_batch(sql)
+            .map_err(|err| io::Error::other(format!("{}: {}", err, sql)))
+    }
+
+    fn write_statement(&mut self, sql: &str) -> io::Result<()> {
+        if self.batch_size > 0 && self.statements_in_batch == 0 {
+            self.execute("BEGIN")?;
+        }
+        self.execute(sql)?;
+        if self.batch_size > 0 {
+            self.statements_in_batch += 1;
+            if self.statements_in_batch == self.batch_size {
+                self.execute("COMMIT")?;
+                self.statements_in_batch = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Sink for SqliteSink {
+    fn write_schema(&mut self) -> io::Result<()> {
+        self.execute(SQLITE_SCHEMA)
+    }
+
+    fn write_changes(&mut self, _event: &Event, changes: &[Change]) -> io::Result<()> {
+        self.write_statement(&changes_to_sql(changes, Dialect::Sqlite))
+    }
+
+    fn write_atomically(&mut self, change_sets: &[(Event, Vec<Change>)]) -> io::Result<()> {
+        self.finish()?;
+        self.execute("BEGIN")?;
+        for (_event, changes) in change_sets {
+            if let Err(err) = self.execute(&changes_to_sql(changes, Dialect::Sqlite)) {
+                self.execute("ROLLBACK")?;
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+            }
+        }
+        self.execute("COMMIT")
+    }
+
+    fn is_in_batch(&self) -> bool {
+        self.statements_in_batch > 0
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.is_in_batch() {
+            self.execute("COMMIT")?;
+            self.statements_in_batch = 0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handling::{event_to_changeset_with_metadata, EventPayload};
+    use crate::test_helpers::event;
+    use ethers::types::Address;
+
+    #[test]
+    fn test_unbatched_statements() {
+        let mut sink = SqlSink::new(vec![], Dialect::Postgres, 0);
+        sink.write_statement("SELECT 1").unwrap();
+        sink.write_statement("SELECT 2").unwrap();
+        assert!(!sink.is_in_batch());
+        sink.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(sink.into_inner()).unwrap(),
+            "SELECT 1;\nSELECT 2;\n"
+        );
+    }
+
+    #[test]
+    fn test_batched_statements() {
+        let mut sink = SqlSink::new(vec![], Dialect::Postgres, 2);
+        sink.write_raw("CREATE TABLE t (x INT);").unwrap();
+        sink.write_statement("SELECT 1").unwrap();
+        assert!(sink.is_in_batch());
+        sink.write_statement("SELECT 2").unwrap();
+        assert!(!sink.is_in_batch());
+        sink.write_statement("SELECT 3").unwrap();
+        sink.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(sink.into_inner()).unwrap(),
+            "CREATE TABLE t (x INT);\n\
+             BEGIN;\nSELECT 1;\nSELECT 2;\nCOMMIT;\n\
+             BEGIN;\nSELECT 3;\nCOMMIT;\n"
+        );
+    }
+
+    #[test]
+    fn test_sqlite_sink() {
+        let directory = tempfile::TempDir::new().unwrap();
+        let path = directory.path().join("gs.sqlite");
+        let mut sink = SqliteSink::open(&path, 2).unwrap();
+        sink.write_statement("INSERT INTO round VALUES (1, '0x1', 1)")
+            .unwrap();
+        assert!(sink.is_in_batch());
+        sink.write_statement("INSERT INTO round VALUES (1, '0x2', 2)")
+            .unwrap();
+        sink.write_statement("INSERT INTO round VALUES (1, '0x3', 3)")
+            .unwrap();
+        sink.finish().unwrap();
+        drop(sink);
+
+        // Reopening keeps the tables and their rows
+        let mut sink = SqliteSink::open(&path, 0).unwrap();
+        sink.write_statement("INSERT INTO round VALUES (1, '0x4', 4)")
+            .unwrap();
+        let count: i64 = sink
+            .connection
+            .query_row("SELECT COUNT(*) FROM round", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 4);
+        assert!(sink
+            .write_statement("INSERT INTO nowhere VALUES (1)")
+            .is_err());
+    }
+
+    fn round_created(block_number: u64, round: u64) -> (Event, Vec<Change>) {
+        let event = event(
+            block_number,
+            EventPayload::RoundCreated {
+                round_address: Address::from_low_u64_be(round),
+            },
+        );
+        let changes = event_to_changeset_with_metadata(&event, None).changes;
+        (event, changes)
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let mut sink = SqlSink::new(vec![], Dialect::Postgres, 0);
+        sink.write_atomically(&[round_created(1, 0xa), round_created(2, 0xb)])
+            .unwrap();
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        assert!(output.starts_with("BEGIN;\nINSERT INTO \"round\""));
+        assert!(output.ends_with(";\nCOMMIT;\n"));
+        assert_eq!(output.lines().count(), 4);
+
+        // The pending batch is committed first
+        let mut sink = SqlSink::new(vec![], Dialect::Postgres, 10);
+        sink.write_statement("SELECT 1").unwrap();
+        sink.write_atomically(&[round_created(1, 0xa)]).unwrap();
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        assert!(output.starts_with("BEGIN;\nSELECT 1;\nCOMMIT;\nBEGIN;\n"));
+    }
+
+    #[test]
+    fn test_sqlite_sink_rolls_back() {
+        let directory = tempfile::TempDir::new().unwrap();
+        let mut sink = SqliteSink::open(&directory.path().join("gs.sqlite"), 0).unwrap();
+        sink.write_atomically(&[round_created(1, 0xa)]).unwrap();
+        // The round is already indexed
+        let err = sink
+            .write_atomically(&[round_created(2, 0xb), round_created(3, 0xa)])
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let count: i64 = sink
+            .connection
+            .query_row("SELECT COUNT(*) FROM round", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+        sink.write_atomically(&[round_created(4, 0xb)]).unwrap();
+    }
+}
@@ -0,0 +1,499 @@
+//! In-memory copy of the indexed tables, built by applying the same changes
+//! the SQL sinks write, for uses that do not need a database: exports, and
+//! checking what the handlers do without running Postgres.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+
+use crate::changes::{Change, Row, Value};
+use crate::event_handling::Event;
+use crate::sink::Sink;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    Integer,
+    /// uint256 values
+    Numeric,
+    Text,
+    Json,
+    /// JSON array of strings, empty unless set
+    List,
+}
+
+pub struct TableSchema {
+    pub name: &'static str,
+    /// Columns identifying a row
+    pub key: &'static [&'static str],
+    pub columns: &'static [(&'static str, ColumnType)],
+}
+
+use ColumnType::*;
+
+/// The tables of `DB_SCHEMA`. `project.search` is left out: it only serves
+/// full-text queries in the database. There is no table of votes, since
+/// Voted events are not handled yet; owners are kept in `project.owners`.
+pub const TABLES: &[TableSchema] = &[
+    TableSchema {
+        name: "project",
+        key: &["chain_id", "project_id"],
+        columns: &[
+            ("chain_id", Integer),
+            ("project_id", Numeric),
+            ("created_at_block", Integer),
+            ("metadata", Json),
+            ("metadata_status", Text),
+            ("metadata_error", Text),
+            ("title", Text),
+            ("description", Text),
+            ("website", Text),
+            ("logo_img", Text),
+            ("banner_img", Text),
+            ("project_twitter", Text),
+            ("user_github", Text),
+            ("project_github", Text),
+            ("credentials", Json),
+            ("owners", List),
+        ],
+    },
+    TableSchema {
+        name: "round",
+        key: &["chain_id", "round_address"],
+        columns: &[
+            ("chain_id", Integer),
+            ("round_address", Text),
+            ("created_at_block", Integer),
+        ],
+    },
+    TableSchema {
+        name: "application",
+        key: &["chain_id", "round_address", "index"],
+        columns: &[
+            ("chain_id", Integer),
+            ("created_at_block", Integer),
+            ("status_updated_at_block", Integer),
+            ("project_id", Text),
+            ("index", Numeric),
+            ("round_address", Text),
+            ("status", Text),
+            ("vote_count", Integer),
+            ("unique_contributor_count", Integer),
+            ("metadata", Json),
+            ("status_snapshots", Json),
+        ],
+    },
+    TableSchema {
+        name: "pending_metadata",
+        key: &["chain_id", "project_id"],
+        columns: &[
+            ("chain_id", Integer),
+            ("project_id", Numeric),
+            ("cid", Text),
+            ("attempts", Integer),
+            ("last_error", Text),
+        ],
+    },
+];
+
+pub fn table_schema(name: &str) -> Option<&'static TableSchema> {
+    TABLES.iter().find(|schema| schema.name == name)
+}
+
+/// Values of a row by column name.
+pub type Record = BTreeMap<String, Value>;
+
+#[derive(Debug, PartialEq)]
+pub enum StoreError {
+    UnknownTable(String),
+    /// An inserted row lacks a column of the table's key
+    MissingKey {
+        table: String,
+        column: String,
+    },
+    DuplicateKey {
+        table: String,
+        key: Vec<Value>,
+    },
+    /// A list change on a column that does not hold a JSON array
+    NotAList {
+        table: String,
+        column: String,
+    },
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::UnknownTable(table) => write!(f, "unknown table {}", table),
+            StoreError::MissingKey { table, column } => {
+                write!(f, "row inserted into {} has no {}", table, column)
+            }
+            StoreError::DuplicateKey { table, key } => {
+                write!(f, "duplicate key {:?} in {}", key, table)
+            }
+            StoreError::NotAList { table, column } => {
+                write!(f, "{}.{} is not a JSON array", table, column)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+/// Rows of each table by key, so that exports and lookups come out in key
+/// order.
+#[derive(Debug, Default)]
+pub struct Store {
+    tables: BTreeMap<&'static str, BTreeMap<Vec<Value>, Record>>,
+}
+
+fn column_names(row: &Row) -> impl Iterator<Item = (String, &Value)> {
+    row.iter()
+        .map(|(column, value)| (column.to_string(), value))
+}
+
+fn list_items(value: Option<&Value>) -> Option<Vec<String>> {
+    match value {
+        Some(Value::Json(json)) => serde_json::from_str(json).ok(),
+        _ => None,
+    }
+}
+
+impl Store {
+    pub fn new() -> Self {
+        Store::default()
+    }
+
+    /// Rows of `table` in key order.
+    pub fn rows(&self, table: &str) -> impl Iterator<Item = &Record> {
+        self.tables
+            .get(table)
+            .into_iter()
+            .flat_map(|rows| rows.values())
+    }
+
+    /// Rows of `table` whose key starts with `prefix`, in key order.
+    pub fn rows_with_prefix<'a>(
+        &'a self,
+        table: &str,
+        prefix: &'a [Value],
+    ) -> impl Iterator<Item = &'a Record> {
+        self.tables
+            .get(table)
+            .into_iter()
+            .flat_map(move |rows| {
+                rows.range(prefix.to_vec()..)
+                    .take_while(move |(key, _)| key.starts_with(prefix))
+            })
+            .map(|(_, record)| record)
+    }
+
+    /// The row of `table` with the values of its key columns, in order.
+    pub fn get(&self, table: &str, key: &[Value]) -> Option<&Record> {
+        self.tables.get(table)?.get(key)
+    }
+
+    pub fn apply_all(&mut self, changes: &[Change]) -> Result<(), StoreError> {
+        changes.iter().try_for_each(|change| self.apply(change))
+    }
+
+    /// Applies `change` the way the database would. Like a SQL `UPDATE`,
+    /// changes to rows that do not exist do nothing.
+    pub fn apply(&mut self, change: &Change) -> Result<(), StoreError> {
+        match change {
+            Change::Insert { table, values } => {
+                let (schema, rows) = self.table(&table.to_string())?;
+                let mut record: Record = column_names(values)
+                    .map(|(column, value)| (column, value.clone()))
+                    .collect();
+                let key = schema
+                    .key
+                    .iter()
+                    .map(|column| {
+                        record
+                            .get(*column)
+                            .cloned()
+                            .ok_or_else(|| StoreError::MissingKey {
+                                table: schema.name.to_string(),
+                                column: column.to_string(),
+                            })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                for (column, column_type) in schema.columns {
+                    let default = match column_type {
+                        List => Value::Json("[]".to_string()),
+                        _ => Value::Null,
+                    };
+                    record.entry(column.to_string()).or_insert(default);
+                }
+                if rows.contains_key(&key) {
+                    return Err(StoreError::DuplicateKey {
+                        table: schema.name.to_string(),
+                        key,
+                    });
+                }
+                rows.insert(key, record);
+            }
+            Change::Update { table, key, values } => {
+                for record in self.matching(&table.to_string(), key)? {
+                    record.extend(
+                        column_names(values).map(|(column, value)| (column, value.clone())),
+                    );
+                }
+            }
+            Change::Upsert { table, key, values } => {
+                if self.matching(&table.to_string(), key)?.is_empty() {
+                    let mut row = key.clone();
+                    row.extend(values.iter().cloned());
+                    return self.apply(&Change::Insert {
+                        table: table.clone(),
+                        values: row,
+                    });
+                }
+                return self.apply(&Change::Update {
+                    table: table.clone(),
+                    key: key.clone(),
+                    values: values.clone(),
+                });
+            }
+            Change::Delete { table, key } => {
+                let (_, rows) = self.table(&table.to_string())?;
+                rows.retain(|_, record| !matches_key(record, key));
+            }
+            Change::AddToList {
+                table,
+                key,
+                column,
+                item,
+            } => self.change_list(&table.to_string(), key, &column.to_string(), |items| {
+                items.push(item.clone())
+            })?,
+            Change::RemoveFromList {
+                table,
+                key,
+                column,
+                item,
+            } => self.change_list(&table.to_string(), key, &column.to_string(), |items| {
+                items.retain(|existing| existing != item)
+            })?,
+        }
+        Ok(())
+    }
+
+    fn table(
+        &mut self,
+        name: &str,
+    ) -> Result<(&'static TableSchema, &mut BTreeMap<Vec<Value>, Record>), StoreError> {
+        let schema =
+            table_schema(name).ok_or_else(|| StoreError::UnknownTable(name.to_string()))?;
+        Ok((schema, self.tables.entry(schema.name).or_default()))
+    }
+
+    /// Rows of `table` whose columns have the values of `key`.
+    fn matching(&mut self, table: &str, key: &Row) -> Result<Vec<&mut Record>, StoreError> {
+        let (schema, rows) = self.table(table)?;
+        let is_primary_key = key.len() == schema.key.len()
+            && key
+                .iter()
+                .zip(schema.key)
+                .all(|((column, _), key_column)| column.to_string() == *key_column);
+        Ok(if is_primary_key {
+            let key: Vec<Value> = key.iter().map(|(_, value)| value.clone()).collect();
+            rows.get_mut(&key).into_iter().collect()
+        } else {
+            rows.values_mut()
+                .filter(|record| matches_key(record, key))
+                .collect()
+        })
+    }
+
+    fn change_list(
+        &mut self,
+        table: &str,
+        key: &Row,
+        column: &str,
+        change: impl Fn(&mut Vec<String>),
+    ) -> Result<(), StoreError> {
+        for record in self.matching(table, key)? {
+            let mut items = list_items(record.get(column)).ok_or_else(|| StoreError::NotAList {
+                table: table.to_string(),
+                column: column.to_string(),
+            })?;
+            change(&mut items);
+            record.insert(
+                column.to_string(),
+                Value::Json(serde_json::to_string(&items).expect("strings can be serialized")),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Sink for Store {
+    fn write_schema(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_changes(&mut self, _event: &Event, changes: &[Change]) -> io::Result<()> {
+        self.apply_all(changes).map_err(io::Error::other)
+    }
+
+    fn write_atomically(&mut self, change_sets: &[(Event, Vec<Change>)]) -> io::Result<()> {
+        let tables = self.tables.clone();
+        for (_event, changes) in change_sets {
+            if let Err(err) = self.apply_all(changes) {
+                self.tables = tables;
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+            }
+        }
+        Ok(())
+    }
+
+    fn is_in_batch(&self) -> bool {
+        false
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn matches_key(record: &Record, key: &Row) -> bool {
+    column_names(key).all(|(column, value)| record.get(&column) == Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_handling::{event_to_changeset_with_metadata, EventPayload, MetaPtr};
+    use crate::test_helpers::event;
+    use ethers::types::{Address, H256, U256};
+
+    fn apply(store: &mut Store, block_number: u64, data: EventPayload, metadata: Option<&str>) {
+        let change_set = event_to_changeset_with_metadata(
+            &event(block_number, data),
+            metadata.map(|metadata| Ok(metadata.to_string())),
+        );
+        store.apply_all(&change_set.changes).unwrap();
+    }
+
+    #[test]
+    fn test_projects() {
+        let mut store = Store::new();
+        let project_id = U256::from(42);
+        let owner = |n| Address::from_low_u64_be(n);
+        apply(
+            &mut store,
+            1,
+            EventPayload::ProjectCreated { project_id },
+            None,
+        );
+        apply(
+            &mut store,
+            2,
+            EventPayload::MetadataUpdated {
+                project_id,
+                meta_ptr: MetaPtr {
+                    pointer: "cid".to_string(),
+                },
+            },
+            Some(r#"{"title":"Foo","description":"Bar"}"#),
+        );
+        for (block_number, n) in [(3, 0xa), (4, 0xb), (5, 0xa)] {
+            apply(
+                &mut store,
+                block_number,
+                EventPayload::OwnerAdded {
+                    project_id,
+                    owner: owner(n),
+                },
+                None,
+            );
+        }
+        apply(
+            &mut store,
+            6,
+            EventPayload::OwnerRemoved {
+                project_id,
+                owner: owner(0xa),
+            },
+            None,
+        );
+
+        let project = store
+            .get("project", &[Value::Integer(1), Value::Numeric(project_id)])
+            .unwrap();
+        assert_eq!(project["created_at_block"], Value::Integer(1));
+        assert_eq!(project["title"], Value::Text("Foo".to_string()));
+        assert_eq!(project["metadata_status"], Value::Text("ok".to_string()));
+        assert_eq!(project["website"], Value::Null);
+        // Like the SQL dialects, removing an owner removes every occurrence
+        assert_eq!(
+            project["owners"],
+            Value::Json(r#"["0x000000000000000000000000000000000000000b"]"#.to_string())
+        );
+        assert_eq!(store.rows("pending_metadata").count(), 0);
+    }
+
+    #[test]
+    fn test_applications_and_rounds() {
+        let mut store = Store::new();
+        // Applications are emitted by the round, 0x…123 like every event
+        let round_created = EventPayload::RoundCreated {
+            round_address: Address::from_low_u64_be(0x123),
+        };
+        apply(&mut store, 1, round_created, None);
+        for index in [1, 0] {
+            apply(
+                &mut store,
+                2,
+                EventPayload::NewProjectApplication {
+                    project_id: H256::from_low_u64_be(0x789),
+                    application_index: U256::from(index),
+                },
+                None,
+            );
+        }
+
+        assert_eq!(store.rows("round").count(), 1);
+        let indexes: Vec<&Value> = store
+            .rows("application")
+            .map(|application| &application["index"])
+            .collect();
+        assert_eq!(
+            indexes,
+            [&Value::Numeric(U256::zero()), &Value::Numeric(U256::one())]
+        );
+        let round = |address| vec![Value::Integer(1), Value::Text(format!("{:#x}", address))];
+        let of_round = round(Address::from_low_u64_be(0x123));
+        assert_eq!(store.rows_with_prefix("application", &of_round).count(), 2);
+        let of_other_round = round(Address::from_low_u64_be(0x124));
+        assert_eq!(
+            store
+                .rows_with_prefix("application", &of_other_round)
+                .count(),
+            0
+        );
+        let application = store.rows("application").next().unwrap();
+        assert_eq!(application["status"], Value::Text("PENDING".to_string()));
+        assert_eq!(application["metadata"], Value::Null);
+
+        let duplicate = store.apply_all(
+            &event_to_changeset_with_metadata(
+                &event(
+                    3,
+                    EventPayload::RoundCreated {
+                        round_address: Address::from_low_u64_be(0x123),
+                    },
+                ),
+                None,
+            )
+            .changes,
+        );
+        assert!(matches!(duplicate, Err(StoreError::DuplicateKey { .. })));
+    }
+}
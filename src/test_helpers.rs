@@ -0,0 +1,49 @@
+//! Fixtures shared by the tests of several modules.
+
+use ethers::types::Address;
+use std::env;
+use tokio_postgres::{Client, NoTls, Transaction};
+
+use crate::event_handling::{Event, EventPayload, DB_SCHEMA};
+
+/// An event of chain 1, emitted by 0x…123 at the first log index of the
+/// block. Tests needing other values set them with `Event { .., ..event() }`.
+pub fn event(block_number: u64, data: EventPayload) -> Event {
+    Event {
+        chain_id: 1,
+        address: Address::from_low_u64_be(0x123),
+        block_number,
+        log_index: 0,
+        data,
+    }
+}
+
+/// Connects to the Postgres server of `GS_INDEX_TEST_DATABASE`, by default
+/// the local one. Tests of the SQL are ignored unless asked for, so that
+/// `cargo test` runs without a database, and fail when there is none.
+pub async fn connect() -> Client {
+    let connection_string = env::var("GS_INDEX_TEST_DATABASE")
+        .unwrap_or_else(|_| "host=localhost user=postgres password=postgres".to_string());
+    let (client, connection) = tokio_postgres::connect(&connection_string, NoTls)
+        .await
+        .unwrap_or_else(|err| panic!("cannot connect to the test database: {}", err));
+    tokio::spawn(connection);
+    client
+}
+
+/// Creates the tables in a transaction that is never committed, so that
+/// tests only see their own rows and leave the database untouched.
+pub async fn transaction_with_schema(client: &mut Client) -> Transaction<'_> {
+    let transaction = client.transaction().await.unwrap();
+    transaction.batch_execute(DB_SCHEMA).await.unwrap();
+    transaction
+}
+
+/// Like `transaction_with_schema`, for tests that hand the client over to
+/// the code under test: the transaction lasts as long as the connection.
+pub async fn client_with_schema() -> Client {
+    let client = connect().await;
+    client.batch_execute("BEGIN").await.unwrap();
+    client.batch_execute(DB_SCHEMA).await.unwrap();
+    client
+}